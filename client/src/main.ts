@@ -1,26 +1,126 @@
 const url: string = "ws://localhost:3000";
 const server_fps: Element = document.getElementById("server-fps")!;
 const client_fps: Element = document.getElementById("client-fps")!;
+const phase_text: Element = document.getElementById("phase")!;
+const scoreboard_text: Element = document.getElementById("scoreboard")!;
 const canvas: HTMLCanvasElement = document.querySelector("canvas")!;
 const ctx: CanvasRenderingContext2D = canvas.getContext("2d")!;
 
 const COLORS = ["red", "blue", "green", "yellow", "purple", "black"] as const;
 
+// see server/src/protocol.rs for the wire format
+const PROTOCOL_VERSION: number = 15;
+
+const enum ClientTag {
+    Hello = 0,
+    ListMatches = 1,
+    CreateMatch = 2,
+    JoinMatch = 3,
+    Input = 4,
+    Click = 5,
+    Ack = 6,
+    SpectateMatch = 7,
+    WatchRoom = 8,
+    AddBot = 9,
+    RemoveBot = 10,
+    SwitchWeapon = 11,
+}
+
+const enum ServerTag {
+    Welcome = 0,
+    Rejected = 1,
+    MatchList = 2,
+    Joined = 3,
+    JoinFailed = 4,
+    Snapshot = 5,
+    RoomGeometry = 6,
+    Spectating = 7,
+    SpectatorSnapshot = 8,
+    MatchPhase = 9,
+    Scoreboard = 10,
+    Killed = 11,
+    TickStats = 12,
+    Kicked = 13,
+}
+
+const PHASES = ["warmup", "live", "ended"] as const;
+
+// milliseconds per server tick, the server's default until its first TickStats, see server/src/scheduler.rs
+let tick_dt: number = 1000 / 60;
+
+const enum Input {
+    UpStart = 0,
+    UpEnd = 1,
+    DownStart = 2,
+    DownEnd = 3,
+    LeftStart = 4,
+    LeftEnd = 5,
+    RightStart = 6,
+    RightEnd = 7,
+    Reload = 8,
+}
+
+type Rect = { width: number, height: number, x: number, y: number };
+// protected users just spawned and can't be killed yet
+type User = Rect & { protected: boolean };
+type Line = { origin_x: number, origin_y: number, end_x: number, end_y: number };
+type Point = { x: number, y: number };
+// the selected weapon, the server ignores clicks while it's cooling down or reloading
+type Loadout = { weapon_idx: number, ammo: number, cooldown_ticks: number, reload_ticks: number };
+type Score = { user_idx: number, points: number, kills: number, deaths: number, wrong_target_hits: number, best_streak: number };
+
 const canvas_size: number = 255;
-const footer_size: number = 1;
+
+// the server can only delta against snapshots from the last 32 ticks
+const history_size: number = 32;
 
 let ws: WebSocket = new WebSocket(url);
-let view: DataView;
+
+let platforms: Rect[] = [];
+let doors: Rect[] = [];
+// only stop shots, users walk in front of them
+let walls: Rect[] = [];
+// block users from every side
+let solids: Rect[] = [];
+let users: Map<number, User> | undefined;
+let bullet_paths: Line[] = [];
+let projectiles: Point[] = [];
+// sent with every snapshot since they move
+let moving_platforms: Rect[] = [];
+let history: Map<number, Map<number, User>> = new Map();
 
 let self_idx: number;
+let match_id: number | undefined;
+let room_idx: number | undefined;
+
+// where the target is while it's revealed for shooting the wrong user, it may be in another room
+let target_reveal: { room_idx: number, rect: Rect } | undefined;
+// the server ignores movement and clicks while stunned
+let stunned: boolean = false;
+let loadout: Loadout | undefined;
+// set while dead, the room keeps being shown until then
+let respawn_tick: number | undefined;
+
+// spectators have no user, they only watch a room and can switch rooms with the number keys
+let spectating: boolean = false;
+
+// users knocked out of a last hunter standing match keep watching the room they died in
+let eliminated: boolean = false;
+
+let phase: number = 0;
+// tick the warmup countdown or time limit runs out
+let phase_end_tick: number | undefined;
+
+// sequence number of the last input or click sent, the server echoes the last one it applied in each snapshot
+let input_seq: number = 0;
+
+// tick of the snapshot on screen, the server resolves clicks against where users were at this tick
+let view_tick: number = 0;
 
-let server_frames: number = 0;
 let client_frames: number = 0;
 
 setInterval(() => {
-    server_fps.textContent = server_frames.toString();
     client_fps.textContent = client_frames.toString();
-    server_frames = 0;
     client_frames = 0;
 }, 1000);
 
@@ -30,205 +130,487 @@ canvas.height = canvas_size;
 ws.binaryType = "arraybuffer";
 
 ws.onopen = () => {
+
     console.log("connected");
+
+    const buf: ArrayBuffer = new ArrayBuffer(3);
+    const view: DataView = new DataView(buf);
+
+    view.setUint8(0, ClientTag.Hello);
+    view.setUint16(1, PROTOCOL_VERSION);
+
+    ws.send(buf);
+
     render();
+
 }
 
 ws.onmessage = (e: MessageEvent) => {
 
-    const buf: ArrayBuffer = e.data;
+    const message: DataView = new DataView(e.data);
+
+    switch(message.getUint8(0)) {
+        case(ServerTag.Welcome):
+            join_match();
+            break;
+        case(ServerTag.Rejected):
+        case(ServerTag.Kicked): {
+            const len: number = message.getUint8(1);
+            alert(new TextDecoder().decode(new Uint8Array(e.data, 2, len)));
+            break;
+        }
+        case(ServerTag.Joined):
+            match_id = message.getUint32(1);
+            self_idx = message.getUint8(5);
+            console.log(`joined match ${match_id}`);
+            break;
+        case(ServerTag.Spectating):
+            eliminated = match_id !== undefined;
+            match_id = message.getUint32(1);
+            spectating = true;
+            console.log(`spectating match ${match_id}`);
+            break;
+        case(ServerTag.JoinFailed):
+            alert("Failed to join match");
+            break;
+        case(ServerTag.RoomGeometry):
+            room_geometry(message);
+            break;
+        case(ServerTag.Snapshot):
+        case(ServerTag.SpectatorSnapshot):
+            snapshot(message);
+            break;
+        case(ServerTag.MatchPhase):
+            phase = message.getUint8(1);
+            phase_end_tick = message.getUint8(2) == 1 ? message.getUint32(3) : undefined;
+            break;
+        case(ServerTag.Scoreboard):
+            scoreboard(message);
+            break;
+        case(ServerTag.Killed):
+            respawn_tick = message.getUint32(1);
+            break;
+        case(ServerTag.TickStats):
+            tick_stats(message);
+            break;
+    }
+
+}
+
+// ticks the server ran over the last second and how long they took
+function tick_stats(message: DataView) {
 
-    view = new DataView(buf);
+    const tick_rate: number = message.getUint16(5);
+    const ticks: number = message.getUint16(7);
+    const skipped: number = message.getUint16(9);
+    const mean_tick_us: number = message.getUint32(11);
+    const max_tick_us: number = message.getUint32(15);
 
-    // footer 
-    self_idx = view.getInt8(view.byteLength-1);
+    tick_dt = 1000 / tick_rate;
 
-    server_frames++;
+    const skipped_text: string = skipped > 0 ? `, ${skipped} skipped` : "";
+
+    server_fps.textContent = `${ticks}/${tick_rate} ticks${skipped_text}, ${mean_tick_us}us avg ${max_tick_us}us max`;
+
+}
+
+// ?match=<id> joins an existing match, ?spectate=<id>&room=<idx> watches one, otherwise a new one is created
+function join_match() {
+
+    const params: URLSearchParams = new URLSearchParams(location.search);
+    const requested_match: string | null = params.get("match");
+    const spectated_match: string | null = params.get("spectate");
+
+    if(spectated_match !== null) {
+
+        const buf: ArrayBuffer = new ArrayBuffer(7);
+        const view: DataView = new DataView(buf);
+
+        view.setUint8(0, ClientTag.SpectateMatch);
+        view.setUint32(1, Number(spectated_match));
+        view.setUint16(5, Number(params.get("room") ?? 0));
+
+        return ws.send(buf);
+
+    }
+
+    if(requested_match === null) {
+        return ws.send(new Uint8Array([ClientTag.CreateMatch]));
+    }
+
+    const buf: ArrayBuffer = new ArrayBuffer(5);
+    const view: DataView = new DataView(buf);
+
+    view.setUint8(0, ClientTag.JoinMatch);
+    view.setUint32(1, Number(requested_match));
+
+    ws.send(buf);
 
 }
 
 document.onkeydown = (e: KeyboardEvent) => {
 
-    if(!ws.OPEN || e.repeat) {
+    if(!ws.OPEN || e.repeat || match_id === undefined) {
         return;
     }
 
+    if(spectating) {
+        return eliminated ? undefined : watch_room(e.key);
+    }
+
     switch(e.key) {
         case("w"):
-            send_key_event(ws, 0);
+            send_input(ws, Input.UpStart);
             break;
         case("s"):
-            send_key_event(ws, 2);
+            send_input(ws, Input.DownStart);
             break;
         case("a"):
-            send_key_event(ws, 4);
+            send_input(ws, Input.LeftStart);
             break;
         case("d"):
-            send_key_event(ws, 6);
+            send_input(ws, Input.RightStart);
+            break;
+        // bots are added at normal difficulty
+        case("b"):
+            ws.send(new Uint8Array([ClientTag.AddBot, 1]));
+            break;
+        case("n"):
+            ws.send(new Uint8Array([ClientTag.RemoveBot]));
+            break;
+        case("r"):
+            send_input(ws, Input.Reload);
+            break;
+        // weapons are numbered from 1 like the keys
+        case("1"):
+        case("2"):
+        case("3"):
+            switch_weapon(Number(e.key) - 1);
             break;
     }
-    
+
 };
 
 document.onkeyup = (e: KeyboardEvent) => {
 
-    if(!ws.OPEN) {
+    if(!ws.OPEN || match_id === undefined || spectating) {
         return;
     }
 
     switch(e.key) {
         case("w"):
-            send_key_event(ws, 1);
+            send_input(ws, Input.UpEnd);
             break;
         case("s"):
-            send_key_event(ws, 3);
+            send_input(ws, Input.DownEnd);
             break;
         case("a"):
-            send_key_event(ws, 5);
+            send_input(ws, Input.LeftEnd);
             break;
         case("d"):
-            send_key_event(ws, 7);
+            send_input(ws, Input.RightEnd);
             break;
     }
-    
+
 };
 
-// we don't really need self_idx rn, could be useful in the future for some reason but i might remove it 
 // coords should be sent to the server divided by the factor, server will calculate normal vector (direction)
 
 canvas.onclick = (e: MouseEvent) => {
 
+    if(match_id === undefined || spectating) {
+        return;
+    }
+
     const x: number = Math.floor(e.offsetX);
     const y: number = Math.floor(e.offsetY);
 
-    const buf: ArrayBuffer = new ArrayBuffer(5);
+    const buf: ArrayBuffer = new ArrayBuffer(13);
     const view: DataView = new DataView(buf);
 
-    view.setUint8(0, 8);
-    view.setUint16(1, x);
-    view.setUint16(3, y);
-    
+    view.setUint8(0, ClientTag.Click);
+    view.setUint32(1, ++input_seq);
+    view.setUint32(5, view_tick);
+    view.setUint16(9, x);
+    view.setUint16(11, y);
+
+    ws.send(buf);
+
+}
+
+function room_geometry(view: DataView) {
+
+    room_idx = view.getUint16(1);
+
+    let idx: number = 3;
+
+    [platforms, idx] = read_list(view, idx, 6, read_rect);
+    [doors, idx] = read_list(view, idx, 6, read_rect);
+    [walls, idx] = read_list(view, idx, 6, read_rect);
+    [solids, idx] = read_list(view, idx, 6, read_rect);
+
+    // snapshots from the previous room can't be used as a baseline anymore
+    history.clear();
+
+}
+
+function snapshot(view: DataView) {
+
+    const tick: number = view.getUint32(1);
+    const is_delta: boolean = view.getUint8(5) == 1;
+
+    let idx: number = 6;
+    let next_users: Map<number, User>;
+
+    if(is_delta) {
+        const baseline: Map<number, User> | undefined = history.get(view.getUint32(idx));
+        idx += 4;
+        if(baseline === undefined) {
+            return console.log(`missing baseline for snapshot ${tick}`);
+        }
+        next_users = new Map(baseline);
+    } else {
+        next_users = new Map();
+    }
+
+    let changed: [number, User][];
+    let removed: number[];
+
+    [changed, idx] = read_list(view, idx, 8, (view, idx) => [view.getUint8(idx), { ...read_rect(view, idx + 1), protected: view.getUint8(idx + 7) == 1 }]);
+    [removed, idx] = read_list(view, idx, 1, (view, idx) => view.getUint8(idx));
+    [bullet_paths, idx] = read_list(view, idx, 8, read_line);
+    [projectiles, idx] = read_list(view, idx, 4, read_point);
+    [moving_platforms, idx] = read_list(view, idx, 6, read_rect);
+
+    for(const [user_idx, user] of changed) {
+        next_users.set(user_idx, user);
+    }
+
+    for(const user_idx of removed) {
+        next_users.delete(user_idx);
+    }
+
+    // footer: user_idx, target_user_idx, last_input_seq, movement (x, y, dx, dy, coyote_ticks, jump_buffer_ticks, stun_ticks),
+    // loadout, target_reveal, killed users are sent snapshots without one until they respawn
+    if(view.getUint8(0) == ServerTag.Snapshot) {
+        respawn_tick = undefined;
+        self_idx = view.getUint8(idx);
+        stunned = view.getUint16(idx + 24) > 0;
+        loadout = {
+            weapon_idx: view.getUint8(idx + 26),
+            ammo: view.getUint16(idx + 27),
+            cooldown_ticks: view.getUint16(idx + 29),
+            reload_ticks: view.getUint16(idx + 31),
+        };
+        target_reveal = view.getUint8(idx + 33) == 1 ? { room_idx: view.getUint16(idx + 34), rect: read_rect(view, idx + 36) } : undefined;
+    }
+
+    users = next_users;
+    view_tick = tick;
+    history.set(tick, next_users);
+    history.delete(tick - history_size);
+
+    const buf: ArrayBuffer = new ArrayBuffer(5);
+    const ack: DataView = new DataView(buf);
+
+    ack.setUint8(0, ClientTag.Ack);
+    ack.setUint32(1, tick);
+
     ws.send(buf);
 
 }
 
+function scoreboard(view: DataView) {
+
+    let idx: number = 1;
+    let winner: number | undefined;
+
+    if(view.getUint8(idx) == 1) {
+        winner = view.getUint8(idx + 1);
+        idx += 1;
+    }
+
+    idx += 1;
+
+    const [scores] = read_list(view, idx, 11, read_score);
+
+    scores.sort((a, b) => b.points - a.points);
+
+    const rows: string[] = scores.map((score) => {
+        const name: string = score.user_idx == self_idx ? `${score.user_idx} (you)` : `${score.user_idx}`;
+        return `${name.padEnd(8)} ${score.points.toString().padStart(6)} ${score.kills.toString().padStart(5)} ${score.deaths.toString().padStart(6)} ${score.wrong_target_hits.toString().padStart(5)} ${score.best_streak.toString().padStart(6)}`;
+    });
+
+    scoreboard_text.textContent = [
+        winner === undefined ? "no winner" : `user ${winner} wins`,
+        "user     points kills deaths wrong streak",
+        ...rows,
+    ].join("\n");
+
+}
+
 function render() {
 
-    if(!view) {
+    if(!users) {
         return requestAnimationFrame(render)
     }
 
     client_frames++;
 
-    ctx.clearRect(0, 0, canvas.width, canvas.height);
+    const seconds_left: string = phase_end_tick === undefined ? "" : ` ${Math.max(0, Math.ceil((phase_end_tick - view_tick) * tick_dt / 1000))}s`;
 
-    let idx: number = 0;
-
-    while(idx < view.byteLength - footer_size) {
-
-        switch(view.getUint8(idx)) {
-            case(0): {
-                user(ctx, view, idx);
-                idx += 10;
-                break;
-            }
-            case(1): {
-                platform(ctx, view, idx);
-                idx += 8;
-                break;
-            }
-            case(3): {
-                door(ctx, view, idx)
-                idx += 8;
-                break;
-            }
-            case(4): {
-                bullet(ctx, view, idx);
-                idx += 10;
-                break;
-            }
-            default:
-                alert("Invalid Object Type")
-        }
+    const reveal_text: string = target_reveal !== undefined && target_reveal.room_idx != room_idx ? ` target in room ${target_reveal.room_idx}` : "";
+
+    const respawn_text: string = respawn_tick === undefined ? "" : ` respawning in ${Math.max(0, Math.ceil((respawn_tick - view_tick) * tick_dt / 1000))}s`;
+
+    const weapon_text: string = loadout === undefined || respawn_tick !== undefined ? "" : ` weapon ${loadout.weapon_idx + 1} ammo ${loadout.ammo}${loadout.reload_ticks > 0 ? " reloading" : ""}`;
 
+    phase_text.textContent = `${PHASES[phase]}${seconds_left}${stunned ? " stunned" : ""}${weapon_text}${reveal_text}${respawn_text}`;
+
+    ctx.clearRect(0, 0, canvas.width, canvas.height);
+
+    walls.forEach((wall) => rect(ctx, wall, "gray"));
+    platforms.forEach((platform) => rect(ctx, platform, COLORS[2]));
+    moving_platforms.forEach((platform) => rect(ctx, platform, COLORS[2]));
+    solids.forEach((solid) => rect(ctx, solid, COLORS[5]));
+    doors.forEach((door) => rect(ctx, door, COLORS[4]));
+    bullet_paths.forEach((path) => bullet(ctx, path));
+    projectiles.forEach((point) => projectile(ctx, point));
+    users.forEach((user, user_idx) => {
+        ctx.globalAlpha = user.protected ? 0.5 : 1;
+        rect(ctx, user, user_idx == self_idx ? COLORS[1] : COLORS[0]);
+    });
+
+    ctx.globalAlpha = 1;
+
+    if(target_reveal !== undefined && target_reveal.room_idx == room_idx) {
+        outline(ctx, target_reveal.rect, COLORS[3]);
     }
 
     requestAnimationFrame(render);
 
 }
 
-function user(ctx: CanvasRenderingContext2D, view: DataView, idx: number) {
-    
-    let sprite_idx: number = view.getUint8(idx + 1); // change back to const
-    const animation_idx: number = view.getUint8(idx + 2);
-    const user_idx: number = view.getUint8(idx + 3);
-    const width: number = view.getUint8(idx + 4);
-    const height: number = view.getUint8(idx + 5);
-    const x: number = view.getUint16(idx + 6);
-    const y: number = view.getUint16(idx + 8);
+// each list is a u16 count followed by its items
+function read_list<T>(view: DataView, idx: number, item_size: number, read: (view: DataView, idx: number) => T): [T[], number] {
+
+    const count: number = view.getUint16(idx);
+    const items: T[] = [];
+
+    idx += 2;
 
-    if(user_idx == self_idx) {
-        sprite_idx = 1;
+    for(let i = 0; i < count; i++) {
+        items.push(read(view, idx));
+        idx += item_size;
     }
 
-    ctx.fillStyle = COLORS[sprite_idx];
-    ctx.fillRect(x, y, width, height);
+    return [items, idx];
 
 }
 
-function platform(ctx: CanvasRenderingContext2D, view: DataView, idx: number) {
-    
-    const sprite_idx: number = view.getUint8(idx + 1);
-    const width: number = view.getUint8(idx + 2);
-    const height: number = view.getUint8(idx + 3);
-    const x: number = view.getUint16(idx + 4);
-    const y: number = view.getUint16(idx + 6);
+function read_rect(view: DataView, idx: number): Rect {
+    return {
+        width: view.getUint8(idx),
+        height: view.getUint8(idx + 1),
+        x: view.getUint16(idx + 2),
+        y: view.getUint16(idx + 4),
+    };
+}
 
-    ctx.fillStyle = COLORS[sprite_idx];
-    ctx.fillRect(x, y, width, height);
+function read_line(view: DataView, idx: number): Line {
+    return {
+        origin_x: view.getUint16(idx),
+        origin_y: view.getUint16(idx + 2),
+        end_x: view.getUint16(idx + 4),
+        end_y: view.getUint16(idx + 6),
+    };
+}
 
+function read_point(view: DataView, idx: number): Point {
+    return {
+        x: view.getUint16(idx),
+        y: view.getUint16(idx + 2),
+    };
 }
 
-function door(ctx: CanvasRenderingContext2D, view: DataView, idx: number) {
+function read_score(view: DataView, idx: number): Score {
+    return {
+        user_idx: view.getUint8(idx),
+        points: view.getInt16(idx + 1),
+        kills: view.getUint16(idx + 3),
+        deaths: view.getUint16(idx + 5),
+        wrong_target_hits: view.getUint16(idx + 7),
+        best_streak: view.getUint16(idx + 9),
+    };
+}
 
-    const sprite_idx: number = view.getUint8(idx + 1);
-    const width: number = view.getUint8(idx + 2);
-    const height: number = view.getUint8(idx + 3);
-    const x: number = view.getUint16(idx + 4);
-    const y: number = view.getUint16(idx + 6);
+function rect(ctx: CanvasRenderingContext2D, rect: Rect, color: string) {
+    ctx.fillStyle = color;
+    ctx.fillRect(rect.x, rect.y, rect.width, rect.height);
+}
 
-    ctx.fillStyle = COLORS[sprite_idx];
-    ctx.fillRect(x, y, width, height);
+function outline(ctx: CanvasRenderingContext2D, rect: Rect, color: string) {
+    ctx.strokeStyle = color;
+    ctx.strokeRect(rect.x - 2, rect.y - 2, rect.width + 4, rect.height + 4);
+}
+
+function bullet(ctx: CanvasRenderingContext2D, path: Line) {
+
+    ctx.strokeStyle = COLORS[5];
+
+    ctx.beginPath();
+
+    ctx.moveTo(path.origin_x, path.origin_y);
+    ctx.lineTo(path.end_x, path.end_y);
+
+    ctx.stroke();
 
 }
 
-function bullet(ctx: CanvasRenderingContext2D, view: DataView, idx: number) {
+function projectile(ctx: CanvasRenderingContext2D, point: Point) {
+    ctx.fillStyle = COLORS[5];
+    ctx.fillRect(point.x - 1, point.y - 1, 3, 3);
+}
 
-    const sprite_idx: number = view.getUint8(idx + 1);
+function send_input(ws: WebSocket, input: Input) {
 
-    const origin_x: number = view.getUint16(idx + 2);
-    const origin_y: number = view.getUint16(idx + 4);
-    const end_x: number = view.getUint16(idx + 6);
-    const end_y: number = view.getUint16(idx + 8);
+    const buf: ArrayBuffer = new ArrayBuffer(6);
+    const view: DataView = new DataView(buf);
 
-    console.log(end_x, end_y);
+    view.setUint8(0, ClientTag.Input);
+    view.setUint32(1, ++input_seq);
+    view.setUint8(5, input);
 
-    ctx.strokeStyle = COLORS[sprite_idx];
+    ws.send(buf);
 
-    ctx.beginPath();
+}
 
-    ctx.moveTo(origin_x, origin_y);
-    ctx.lineTo(end_x, end_y);
+function watch_room(key: string) {
 
-    ctx.stroke();
+    const room_idx: number = Number(key);
+
+    if(key.length != 1 || Number.isNaN(room_idx)) {
+        return;
+    }
+
+    const buf: ArrayBuffer = new ArrayBuffer(3);
+    const view: DataView = new DataView(buf);
+
+    view.setUint8(0, ClientTag.WatchRoom);
+    view.setUint16(1, room_idx);
+
+    ws.send(buf);
 
 }
 
-function send_key_event(ws: WebSocket, byte: number) {
+function switch_weapon(weapon_idx: number) {
 
-    const buf: Uint8Array = new Uint8Array(1);
+    const buf: ArrayBuffer = new ArrayBuffer(6);
+    const view: DataView = new DataView(buf);
 
-    buf[0] = byte;
+    view.setUint8(0, ClientTag.SwitchWeapon);
+    view.setUint32(1, ++input_seq);
+    view.setUint8(5, weapon_idx);
 
     ws.send(buf);
 
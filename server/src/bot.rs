@@ -0,0 +1,307 @@
+use rand::{ Rng, SeedableRng };
+use crate::{ client, entity, nav, ray, room, simulation, user };
+
+// a user without a websocket, it reads the simulation and answers with the same messages a client would send
+pub struct Bot {
+    pub user_idx: usize,
+    pub difficulty: Difficulty,
+    seq: u32,
+    // tick the target came into view, shots wait out the reaction time after it
+    sighted_tick: Option<u32>,
+    next_shot_tick: u32,
+    holding_left: bool,
+    holding_right: bool,
+    // where a jump or fall is headed, kept until landing
+    air_goal_x: Option<f32>,
+    // to notice being stuck against something while running
+    last_x: f32,
+    stuck_ticks: u32,
+    // aim error, seeded from the match so the same match misses the same way
+    rng: rand_chacha::ChaCha8Rng,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Difficulty {
+    // ticks between seeing the target and shooting at it
+    pub reaction_ticks: u32,
+    pub fire_interval_ticks: u32,
+    // max distance a shot lands from the target's center
+    pub aim_error: f32,
+    pub max_range: f32,
+}
+
+// how close (in pixels) a bot needs to get to where it's going
+const ARRIVE_DISTANCE: f32 = 4.0;
+const STUCK_TICKS: u32 = 10;
+// horizontal distance kept from a target on the same surface, standing on top of it blocks the shot
+const STANDOFF_DISTANCE: f32 = 40.0;
+
+// what to do this tick to follow a path
+struct Steer {
+    goal_x: f32,
+    jump: bool,
+    drop: bool,
+}
+
+impl Difficulty {
+
+    pub const EASY: Difficulty = Difficulty { reaction_ticks: 30, fire_interval_ticks: 90, aim_error: 12.0, max_range: 120.0 };
+    pub const NORMAL: Difficulty = Difficulty { reaction_ticks: 15, fire_interval_ticks: 45, aim_error: 6.0, max_range: 160.0 };
+    pub const HARD: Difficulty = Difficulty { reaction_ticks: 5, fire_interval_ticks: 20, aim_error: 2.0, max_range: 200.0 };
+
+    pub fn from_level(level: u8) -> Self {
+        match level {
+            0 => Self::EASY,
+            1 => Self::NORMAL,
+            _ => Self::HARD,
+        }
+    }
+
+}
+
+impl Bot {
+
+    pub fn new(user_idx: usize, difficulty: Difficulty, seed: u64) -> Self {
+
+        // a stream of its own, bots in the same match don't miss in lockstep
+        let mut rng: rand_chacha::ChaCha8Rng = rand_chacha::ChaCha8Rng::seed_from_u64(seed);
+
+        rng.set_stream(user_idx as u64);
+
+        Self {
+            user_idx,
+            difficulty,
+            seq: 0,
+            sighted_tick: None,
+            next_shot_tick: 0,
+            holding_left: false,
+            holding_right: false,
+            air_goal_x: None,
+            last_x: 0.0,
+            stuck_ticks: 0,
+            rng,
+        }
+
+    }
+
+    // inputs for the next tick
+    pub fn think(&mut self, simulation: &simulation::Simulation, nav: &nav::NavGraph) -> Vec<client::Message> {
+
+        let mut msgs: Vec<client::Message> = Vec::new();
+
+        // respawned users start out holding nothing
+        let user: &user::User = match simulation.users[self.user_idx].as_ref() {
+            Some(user) => user,
+            None => {
+                self.holding_left = false;
+                self.holding_right = false;
+                self.air_goal_x = None;
+                self.sighted_tick = None;
+                return msgs;
+            }
+        };
+
+        let entity: &entity::Entity = &user.dynamic_entity.entity;
+
+        if self.holding_left || self.holding_right {
+            if (entity.x - self.last_x).abs() < 0.5 {
+                self.stuck_ticks += 1;
+            } else {
+                self.stuck_ticks = 0;
+            }
+        }
+
+        self.last_x = entity.x;
+
+        // a lone user hunts themselves, there's nothing to do
+        let target: &user::User = match simulation.users[user.target_user_idx].as_ref() {
+            Some(target) if target.idx != user.idx => target,
+            _ => {
+                self.sighted_tick = None;
+                self.steer(&mut msgs, user, Steer { goal_x: entity.x, jump: false, drop: false });
+                return msgs;
+            }
+        };
+
+        let steer: Steer = self.follow_path(simulation, nav, user, target);
+
+        self.steer(&mut msgs, user, steer);
+
+        match target.room_idx == user.room_idx && line_of_sight(simulation, user, target, self.difficulty.max_range) {
+            true => {
+                self.sighted_tick.get_or_insert(simulation.tick);
+            }
+            false => self.sighted_tick = None,
+        }
+
+        let reacted: bool = self.sighted_tick.is_some_and(|sighted_tick| simulation.tick >= sighted_tick + self.difficulty.reaction_ticks);
+
+        // shots at a protected target are wasted
+        if reacted && target.protected_ticks == 0 && simulation.tick >= self.next_shot_tick {
+
+            let (x, y) = center(&target.dynamic_entity.entity);
+
+            let aim_x: f32 = x + self.rng.gen_range(-self.difficulty.aim_error..=self.difficulty.aim_error);
+            let aim_y: f32 = y + self.rng.gen_range(-self.difficulty.aim_error..=self.difficulty.aim_error);
+
+            self.seq += 1;
+            msgs.push(client::Message::Click(self.user_idx, self.seq, simulation.tick, aim_x, aim_y));
+
+            self.next_shot_tick = simulation.tick + self.difficulty.fire_interval_ticks;
+
+        }
+
+        msgs
+
+    }
+
+    // heads for the first edge of the path to the target's surface, then takes it
+    fn follow_path(&mut self, simulation: &simulation::Simulation, nav: &nav::NavGraph, user: &user::User, target: &user::User) -> Steer {
+
+        let entity: &entity::Entity = &user.dynamic_entity.entity;
+
+        if user.grounded() {
+            self.air_goal_x = None;
+        }
+
+        // keep going the same way until landing
+        if let Some(goal_x) = self.air_goal_x {
+            return Steer { goal_x, jump: false, drop: false };
+        }
+
+        let path: Option<Vec<nav::Edge>> = nav.surface_under(user.room_idx, entity)
+            .zip(nav.surface_under(target.room_idx, &target.dynamic_entity.entity))
+            .and_then(|(from, to)| nav.find_path(from, to));
+
+        let edge: nav::Edge = match path.as_ref().and_then(|path| path.first()) {
+            Some(edge) => *edge,
+            // already on the target's surface, or there's no way there
+            None => {
+
+                let target_x: f32 = target.dynamic_entity.entity.x;
+                let x_max: f32 = simulation.rooms[user.room_idx].bounds.x_max - entity.width;
+
+                let goal_x: f32 = match entity.x >= target_x {
+                    true if target_x + STANDOFF_DISTANCE <= x_max => target_x + STANDOFF_DISTANCE,
+                    _ if target_x - STANDOFF_DISTANCE >= 0.0 => target_x - STANDOFF_DISTANCE,
+                    _ => target_x + STANDOFF_DISTANCE,
+                };
+
+                return Steer { goal_x, jump: false, drop: false };
+
+            }
+        };
+
+        if (entity.x - edge.from_x).abs() > ARRIVE_DISTANCE {
+            return Steer { goal_x: edge.from_x, jump: false, drop: false };
+        }
+
+        match edge.kind {
+            nav::EdgeKind::Jump => {
+                self.air_goal_x = Some(edge.to_x);
+                Steer { goal_x: edge.to_x, jump: true, drop: false }
+            }
+            nav::EdgeKind::Fall => {
+                self.air_goal_x = Some(edge.to_x);
+                Steer { goal_x: edge.to_x, jump: false, drop: false }
+            }
+            nav::EdgeKind::Drop => Steer { goal_x: edge.to_x, jump: false, drop: true },
+            nav::EdgeKind::Door(door_idx) => {
+                // walk into the door, the room changes on contact
+                let door: &entity::Entity = &simulation.rooms[user.room_idx].doors[door_idx].entity;
+                Steer { goal_x: door.x + door.width * 0.5, jump: false, drop: false }
+            }
+        }
+
+    }
+
+    fn steer(&mut self, msgs: &mut Vec<client::Message>, user: &user::User, steer: Steer) {
+
+        let x: f32 = user.dynamic_entity.entity.x;
+
+        let hold_left: bool = steer.goal_x < x - ARRIVE_DISTANCE;
+        let hold_right: bool = steer.goal_x > x + ARRIVE_DISTANCE;
+
+        if hold_left != self.holding_left {
+            self.seq += 1;
+            msgs.push(match hold_left {
+                true => client::Message::LeftStart(self.user_idx, self.seq),
+                false => client::Message::LeftEnd(self.user_idx, self.seq),
+            });
+            self.holding_left = hold_left;
+            self.stuck_ticks = 0;
+        }
+
+        if hold_right != self.holding_right {
+            self.seq += 1;
+            msgs.push(match hold_right {
+                true => client::Message::RightStart(self.user_idx, self.seq),
+                false => client::Message::RightEnd(self.user_idx, self.seq),
+            });
+            self.holding_right = hold_right;
+            self.stuck_ticks = 0;
+        }
+
+        if !user.grounded() {
+            return;
+        }
+
+        if steer.jump || self.stuck_ticks >= STUCK_TICKS {
+            self.seq += 1;
+            msgs.push(client::Message::UpStart(self.user_idx, self.seq));
+            self.stuck_ticks = 0;
+        } else if steer.drop && !user.holding_down {
+            self.seq += 1;
+            msgs.push(client::Message::DownStart(self.user_idx, self.seq));
+        }
+
+    }
+
+}
+
+fn center(entity: &entity::Entity) -> (f32, f32) {
+    (entity.x + entity.width * 0.5, entity.y + entity.height * 0.5)
+}
+
+// whether a shot at the target's center would reach it before a platform (moving ones where they are now), a wall, a solid or another user
+fn line_of_sight(simulation: &simulation::Simulation, user: &user::User, target: &user::User, max_range: f32) -> bool {
+
+    let (x, y) = center(&target.dynamic_entity.entity);
+    let (origin_x, origin_y) = center(&user.dynamic_entity.entity);
+
+    let distance: f32 = ((x - origin_x).powi(2) + (y - origin_y).powi(2)).sqrt();
+
+    // overlapping centers have no direction to shoot in
+    if distance < 1.0 || distance > max_range {
+        return false;
+    }
+
+    let ray: ray::Ray = match ray::Ray::from_entity_and_position(&user.dynamic_entity.entity, x, y) {
+        Some(ray) => ray,
+        None => return false,
+    };
+
+    let target_distance: f32 = match ray.entry(&target.dynamic_entity.entity) {
+        Some(target_distance) => target_distance,
+        None => return false,
+    };
+
+    let room: &room::Room = &simulation.rooms[user.room_idx];
+
+    let blocked_by_platform: bool = room.platforms
+        .iter()
+        .chain(room.walls.iter())
+        .chain(room.solids.iter())
+        .cloned()
+        .chain(room.moving_platforms.iter().map(|moving_platform| moving_platform.entity_at(simulation.tick)))
+        .any(|platform| ray.entry(&platform).is_some_and(|distance| distance < target_distance));
+
+    let blocked_by_user: bool = simulation.users
+        .iter()
+        .flatten()
+        .filter(|other| other.room_idx == user.room_idx && other.idx != user.idx && other.idx != target.idx)
+        .any(|other| ray.entry(&other.dynamic_entity.entity).is_some_and(|distance| distance < target_distance));
+
+    !blocked_by_platform && !blocked_by_user
+
+}
@@ -1,12 +1,17 @@
-use crate::{ ray, room, user::{self, User} };
+use crate::{ entity, protocol, ray, room, user };
 
 pub struct Bullet {
     pub user_idx: usize, 
-    pub target_user_idx: usize,
     pub room_idx: usize,
+    pub rewind_tick: u32,
     pub ray: ray::Ray,
+    // how far it goes when it misses
+    pub range: f32,
 }
 
+// (user idx, hitbox) of every user in a room at the end of a tick
+pub type Hitboxes = Vec<(usize, entity::Entity)>;
+
 pub struct BulletPath {
     pub origin_x: f32,
     pub origin_y: f32, 
@@ -16,86 +21,111 @@ pub struct BulletPath {
 
 impl Bullet {
 
-    pub fn tick(&self, users: &mut [Option<user::User>]) -> BulletPath {
+    // rooms a single shot can pass through one after another, so doors linked to each other can't loop forever
+    const MAX_ROOMS: usize = 4;
 
-        let room: &room::Room = &room::ROOMS[self.room_idx];
+    // the path the bullet travelled through each room it crossed as (room idx, path), and the user it hit, if any
+    pub fn tick(&self, users: &[Option<user::User>], rooms: &[room::Room], rooms_mut: &[room::RoomMut]) -> (Vec<(usize, BulletPath)>, Option<usize>) {
 
-        let mut intersection: Option<ray::Intersection> = None;
-        let mut min_distance: f32 = f32::INFINITY;
+        let mut bullet_paths: Vec<(usize, BulletPath)> = Vec::new();
 
-        for idx in 0..users.len() {
+        let mut room_idx: usize = self.room_idx;
+        let mut ray: ray::Ray = self.ray;
+        let mut range: f32 = self.range;
 
-            if idx == self.user_idx {
-                continue;
-            }
+        for _ in 0..Self::MAX_ROOMS {
 
-            let user: &user::User = match users[idx].as_ref() {
-                Some(user) => user,
-                None => continue,
-            };
+            let room: &room::Room = &rooms[room_idx];
+            let rewound_hitboxes: Option<&Hitboxes> = rooms_mut[room_idx].hitboxes.get(self.rewind_tick);
 
-            if user.room_idx != self.room_idx {
-                continue;
-            }
+            // where the moving platforms were when the shooter saw them
+            let moving_platforms: Vec<entity::Entity> = room.moving_platforms
+                .iter()
+                .map(|moving_platform| moving_platform.entity_at(self.rewind_tick))
+                .collect();
 
-            let intersection_distance: Option<f32> = self.ray.intersection(&user.dynamic_entity.entity);
+            let mut intersection: Option<ray::Intersection> = None;
+            // a miss ends where it leaves the room
+            let mut min_distance: f32 = range.min(ray.intersection(&room.bounds.to_entity()).unwrap_or(0.0));
 
-            if let Some(distance) = intersection_distance {
-                if distance < min_distance {
-                    intersection = Some(ray::Intersection { distance, variant: ray::IntersectionVariant::User(idx) });
-                    min_distance = distance;
+            for (idx, user) in users.iter().enumerate() {
+
+                if idx == self.user_idx {
+                    continue;
                 }
-            }
 
-        }
+                let user: &user::User = match user.as_ref() {
+                    Some(user) => user,
+                    None => continue,
+                };
 
-        for entity in room.platforms {
+                if user.room_idx != room_idx {
+                    continue;
+                }
 
-            let intersection_distance: Option<f32> = self.ray.intersection(entity);
+                // users that (re)spawned in this room after the rewind tick are hit where they are now
+                let hitbox: &entity::Entity = rewound_hitboxes
+                    .filter(|_| self.rewind_tick >= user.room_entry_tick)
+                    .and_then(|hitboxes| hitboxes.iter().find(|(user_idx, _)| *user_idx == idx))
+                    .map_or(&user.dynamic_entity.entity, |(_, hitbox)| hitbox);
 
-            if let Some(distance) = intersection_distance {
-                if distance < min_distance {
-                    intersection = Some(ray::Intersection { distance, variant: ray::IntersectionVariant::Platform });
-                    min_distance = distance;
+                if let Some(distance) = ray.entry(hitbox) {
+                    if distance < min_distance {
+                        intersection = Some(ray::Intersection { distance, variant: ray::IntersectionVariant::User(idx) });
+                        min_distance = distance;
+                    }
                 }
+
             }
 
-        }
+            let obstacles = room.platforms
+                .iter()
+                .map(|entity| (entity, ray::IntersectionVariant::Platform))
+                .chain(room.walls.iter().map(|entity| (entity, ray::IntersectionVariant::Wall)))
+                .chain(room.solids.iter().map(|entity| (entity, ray::IntersectionVariant::Solid)))
+                .chain(moving_platforms.iter().map(|entity| (entity, ray::IntersectionVariant::Platform)))
+                .chain(room.doors.iter().enumerate().map(|(door_idx, door)| (&door.entity, ray::IntersectionVariant::Door(door_idx))));
+
+            for (entity, variant) in obstacles {
 
-        let distance: f32 = match intersection {
-            //None => f32::max(room.bounds.x_max, room.bounds.y_max) * std::f32::consts::SQRT_2,
-            None => 200.0,
-            Some(ray::Intersection { variant, distance }) => {
+                if let Some(distance) = ray.entry(entity) {
+                    if distance < min_distance {
+                        intersection = Some(ray::Intersection { distance, variant });
+                        min_distance = distance;
+                    }
+                }
 
-                if let ray::IntersectionVariant::User(idx) = variant {
+            }
 
-                    if idx == self.target_user_idx {
+            bullet_paths.push((room_idx, BulletPath::from_ray(&ray, min_distance)));
 
-                        // add code to take the target's target
-                        
-                        let killed_user: user::User = users[idx].take().unwrap();
+            match intersection {
+                Some(ray::Intersection { variant: ray::IntersectionVariant::User(idx), .. }) => return (bullet_paths, Some(idx)),
+                // doors are walked through sideways, so only shots with a sideways direction go through them
+                Some(ray::Intersection { variant: ray::IntersectionVariant::Door(door_idx), distance }) if ray.direction_x != 0.0 => {
 
-                        users[self.user_idx].as_mut().unwrap().target_user_idx = killed_user.target_user_idx;
-                        
-                        // respawn shot user
-                        //user.respawn(user.room_idx);
-                        users[idx] = Some(user::User::new(
-                            killed_user.idx, 
-                            killed_user.room_idx, 
-                            User::get_target_idx(users, idx), 
-                            killed_user.send_to_client,
-                        ));
+                    let door: &room::Door = &room.doors[door_idx];
+                    let linked_door: &entity::Entity = &rooms[door.room_idx].doors[door.door_idx].entity;
 
-                    }
+                    let y: f32 = ray.origin_y + distance * ray.direction_y;
 
-                }
+                    // out the far side of the linked door, as far down it as it went into this one
+                    ray.origin_x = match ray.direction_x > 0.0 {
+                        true => linked_door.x + linked_door.width,
+                        false => linked_door.x,
+                    };
+                    ray.origin_y = linked_door.y + (y - door.entity.y);
 
-                distance
+                    range -= distance;
+                    room_idx = door.room_idx;
 
+                }
+                _ => return (bullet_paths, None),
             }
-        };
 
-        BulletPath::from_bullet(self, distance)
+        }
+
+        (bullet_paths, None)
 
     }
 
@@ -103,13 +133,22 @@ impl Bullet {
 
 impl BulletPath {
 
-    fn from_bullet(bullet: &Bullet, magnitude: f32) -> Self {
+    pub fn to_line(&self) -> protocol::Line {
+        protocol::Line {
+            origin_x: self.origin_x as u16,
+            origin_y: self.origin_y as u16,
+            end_x: self.end_x as u16,
+            end_y: self.end_y as u16,
+        }
+    }
+
+    fn from_ray(ray: &ray::Ray, magnitude: f32) -> Self {
 
-        let origin_x: f32 = bullet.ray.origin_x;
-        let origin_y: f32 = bullet.ray.origin_y;
+        let origin_x: f32 = ray.origin_x;
+        let origin_y: f32 = ray.origin_y;
 
-        let end_x: f32 = origin_x + magnitude * bullet.ray.direction_x;
-        let end_y: f32 = origin_y + magnitude * bullet.ray.direction_y;
+        let end_x: f32 = origin_x + magnitude * ray.direction_x;
+        let end_y: f32 = origin_y + magnitude * ray.direction_y;
 
         Self {
             origin_x,
@@ -3,82 +3,249 @@ use tokio::sync::oneshot;
 use tokio::sync::mpsc;
 use futures_util::{ SinkExt, StreamExt };
 use tokio_tungstenite::tungstenite;
+use std::sync::Arc;
+use crate::{ lobby, log, metrics, protocol };
 
 #[derive(Debug)]
 pub enum Message {
     Connect { 
         send_idx_to_client: oneshot::Sender<usize>,
         send_to_client: mpsc::Sender<Vec<u8>>,
+        mailbox: Mailbox,
     },
-    UpStart(usize),
-    UpEnd(usize),
-    DownStart(usize),
-    DownEnd(usize),
-    LeftStart(usize),
-    LeftEnd(usize),
-    RightStart(usize),
-    RightEnd(usize),
-    Click(usize, f32, f32),
+    Spectate {
+        room_idx: usize,
+        send_idx_to_client: oneshot::Sender<usize>,
+        send_to_client: mpsc::Sender<Vec<u8>>,
+        mailbox: Mailbox,
+    },
+    // (user idx, input seq, ..)
+    UpStart(usize, u32),
+    UpEnd(usize, u32),
+    DownStart(usize, u32),
+    DownEnd(usize, u32),
+    LeftStart(usize, u32),
+    LeftEnd(usize, u32),
+    RightStart(usize, u32),
+    RightEnd(usize, u32),
+    Click(usize, u32, u32, f32, f32),
+    Reload(usize, u32),
+    SwitchWeapon(usize, u32, usize),
+    Ack(usize, u32),
+    // any user in the match can add or remove bots
+    AddBot(u8),
+    RemoveBot,
+    // sent once when the socket closes or errors, the idx is not used again by this client
+    Disconnect(usize),
+    // (spectator idx, ..), spectators are indexed separately from users
+    Watch(usize, usize),
+    SpectatorAck(usize, u32),
+    SpectatorDisconnect(usize),
 }
 
 pub struct Client {
     idx: usize,
-    ws: tokio_tungstenite::WebSocketStream<tokio::net::TcpStream>,
+    spectator: bool,
+    ws: WebSocket,
     receive_from_game: mpsc::Receiver<Vec<u8>>,
+    mailbox: Mailbox,
     send_to_game: mpsc::Sender<Message>,
+    // the connection's span with the match and idx it joined as
+    span: log::Span,
+}
+
+// the newest room geometry and snapshot for a client and whether it's been kicked, shared by the game and the client
+// so none of them wait behind messages in the channel, anything the client hasn't sent by the time a newer one comes
+// in is dropped
+#[derive(Debug, Clone, Default)]
+pub struct Mailbox(Arc<(std::sync::Mutex<Mail>, tokio::sync::Notify)>);
+
+#[derive(Debug, Default)]
+struct Mail {
+    geometry: Option<Vec<u8>>,
+    snapshot: Option<Vec<u8>>,
+    kick_reason: Option<String>,
+}
+
+enum Outgoing {
+    Send(Vec<u8>),
+    Kicked(String),
 }
 
+type WebSocket = tokio_tungstenite::WebSocketStream<tokio::net::TcpStream>;
+
 impl Client {
 
-    pub async fn init(stream: tokio::net::TcpStream, send_to_game: mpsc::Sender<Message>) {
+    pub async fn init(stream: tokio::net::TcpStream, send_to_lobby: mpsc::Sender<lobby::Message>, channel_size: usize, span: log::Span) {
 
-        let ws: tokio_tungstenite::WebSocketStream<tokio::net::TcpStream> = match tokio_tungstenite::accept_async(stream).await {
+        let mut ws: WebSocket = match tokio_tungstenite::accept_async(stream).await {
             Ok(ws) => ws,
-            Err(_) => return println!("failed to connect to websocket"),
+            Err(err) => return span.warn("failed to connect to websocket", &[("err", &err)]),
         };
 
-        let (
-            send_to_client, 
-            receive_from_game,
-        ) = mpsc::channel(100);
+        let rejection: Option<String> = match Self::receive(&mut ws, &span).await {
+            Some(protocol::ClientMessage::Hello { version }) if version == protocol::VERSION => None,
+            Some(protocol::ClientMessage::Hello { version }) => Some(format!("server speaks protocol version {}, client speaks {}", protocol::VERSION, version)),
+            Some(_) => Some(String::from("expected hello")),
+            None => return,
+        };
 
-        let (
-            send_idx_to_client,
-            receive_idx_from_game 
-        ) = oneshot::channel();
+        if let Some(reason) = rejection {
+            span.info("rejected", &[("reason", &reason)]);
+            let _ = Self::send(&mut ws, protocol::ServerMessage::Rejected { reason }, &span).await;
+            let _ = ws.close(None).await;
+            return;
+        }
 
-        if let Err(err) = send_to_game.send(Message::Connect { 
-            send_idx_to_client, 
-            send_to_client, 
-        }).await {
-            return println!("failed to connect to game: {:#?}", err);
+        if Self::send(&mut ws, protocol::ServerMessage::Welcome { version: protocol::VERSION }, &span).await.is_none() {
+            return;
         }
 
-        let idx: usize = match receive_idx_from_game.await {
-            Ok(idx) => idx,
-            Err(err) => return println!("error receiving index: {:#?}", err),
+        // lobby handshake, the client stays here until it has joined a match
+        let (lobby_match, idx, receive_from_game, mailbox, spectate_room_idx) = loop {
+
+            let (
+                send_match_to_client,
+                receive_match_from_lobby,
+            ) = oneshot::channel();
+
+            let (lobby_msg, spectate_room_idx): (lobby::Message, Option<usize>) = match Self::receive(&mut ws, &span).await {
+                Some(protocol::ClientMessage::ListMatches) => {
+
+                    let (
+                        send_list_to_client,
+                        receive_list_from_lobby,
+                    ) = oneshot::channel();
+
+                    if let Err(err) = send_to_lobby.send(lobby::Message::List { send_list_to_client }).await {
+                        return span.error("failed to reach lobby", &[("err", &err)]);
+                    }
+
+                    let match_ids: Vec<u32> = match receive_list_from_lobby.await {
+                        Ok(match_ids) => match_ids,
+                        Err(err) => return span.error("lobby dropped the match list", &[("err", &err)]),
+                    };
+
+                    if Self::send(&mut ws, protocol::ServerMessage::MatchList { match_ids }, &span).await.is_none() {
+                        return;
+                    }
+
+                    continue;
+
+                }
+                Some(protocol::ClientMessage::CreateMatch) => (lobby::Message::Create { send_match_to_client }, None),
+                Some(protocol::ClientMessage::JoinMatch { match_id }) => (lobby::Message::Join { match_id, send_match_to_client }, None),
+                Some(protocol::ClientMessage::SpectateMatch { match_id, room_idx }) => (lobby::Message::Join { match_id, send_match_to_client }, Some(room_idx as usize)),
+                Some(msg) => return span.warn("unexpected client message before joining a match", &[("msg", &format!("{:?}", msg))]),
+                None => return,
+            };
+
+            if let Err(err) = send_to_lobby.send(lobby_msg).await {
+                return span.error("failed to reach lobby", &[("err", &err)]);
+            }
+
+            let lobby_match: lobby::Match = match receive_match_from_lobby.await {
+                Ok(Some(lobby_match)) => lobby_match,
+                Ok(None) => {
+                    // match doesn't exist or the lobby is full, the client can try again
+                    if Self::send(&mut ws, protocol::ServerMessage::JoinFailed, &span).await.is_none() {
+                        return;
+                    }
+                    continue;
+                }
+                Err(err) => return span.error("lobby dropped the match", &[("err", &err)]),
+            };
+
+            match Self::connect(&lobby_match.send_to_game, spectate_room_idx, channel_size).await {
+                Some((idx, receive_from_game, mailbox)) => break (lobby_match, idx, receive_from_game, mailbox, spectate_room_idx),
+                None => {
+                    // match is full, just ended, or the spectated room doesn't exist
+                    if Self::send(&mut ws, protocol::ServerMessage::JoinFailed, &span).await.is_none() {
+                        return;
+                    }
+                }
+            }
+
+        };
+
+        let joined: protocol::ServerMessage = match spectate_room_idx {
+            Some(_) => protocol::ServerMessage::Spectating { match_id: lobby_match.match_id },
+            None => protocol::ServerMessage::Joined { match_id: lobby_match.match_id, user_idx: idx as u8 },
+        };
+
+        let span: log::Span = match spectate_room_idx {
+            Some(_) => span.with("match_id", lobby_match.match_id).with("spectator_idx", idx),
+            None => span.with("match_id", lobby_match.match_id).with("user_idx", idx),
+        };
+
+        span.info("joined", &[]);
+
+        let connected: &metrics::Gauge = match spectate_room_idx {
+            Some(_) => &metrics::METRICS.connected_spectators,
+            None => &metrics::METRICS.connected_users,
         };
 
+        connected.inc();
+
         let mut client: Self = Self {
             idx,
+            spectator: spectate_room_idx.is_some(),
             ws,
             receive_from_game,
-            send_to_game,
+            mailbox,
+            send_to_game: lobby_match.send_to_game,
+            span,
         };
 
+        if Self::send(&mut client.ws, joined, &client.span).await.is_some() {
+            client.run().await;
+        }
+
+        client.disconnect().await;
+
+        client.span.info("left", &[]);
+
+        connected.dec();
+
+    }
+
+    async fn run(&mut self) {
+
+        let client: &mut Self = self;
+
         // use tokio select to create 2 tasks, one for passing on client messages (below), and one for listening for render commands from game
         loop {
             tokio::select! {
 
+                // a kicked client is told before the game's closed channel is noticed
+                biased;
+
+                outgoing = client.mailbox.next() => {
+
+                    let buf: Vec<u8> = match outgoing {
+                        Outgoing::Send(buf) => buf,
+                        Outgoing::Kicked(reason) => {
+                            let _ = Self::send(&mut client.ws, protocol::ServerMessage::Kicked { reason }, &client.span).await;
+                            return;
+                        }
+                    };
+
+                    if let Err(err) = client.ws.send(tungstenite::Message::binary(buf)).await {
+                        return client.span.warn("failed to send on websocket stream", &[("err", &err)]);
+                    }
+
+                }
+
                 buf = client.receive_from_game.recv() => {
 
                     let buf: Vec<u8> = match buf {
                         Some(buf) => buf,
-                        None => return println!("no render buffer found"),
+                        // the game ended or dropped the client
+                        None => return client.span.debug("game closed the channel", &[]),
                     };
 
                     if let Err(err) = client.ws.send(tungstenite::Message::binary(buf)).await {
-                        return println!("failed to send on websocket stream: {:#?}", err);
+                        return client.span.warn("failed to send on websocket stream", &[("err", &err)]);
                     }
 
                 }
@@ -87,22 +254,34 @@ impl Client {
                     
                     let ws_msg: tungstenite::Message = match ws_msg {
                         Some(Ok(ws_msg)) => ws_msg,
-                        Some(Err(err)) => return println!("failed to listen on websocket stream: {:#?}", err),
-                        None => return println!("no tungstenite message found"),
+                        Some(Err(err)) => return client.span.warn("failed to listen on websocket stream", &[("err", &err)]),
+                        None => return client.span.debug("websocket closed", &[]),
                     };
 
                     let buf: Vec<u8> = match ws_msg {
                         tungstenite::Message::Binary(buf) => buf,
-                        _ => return println!("invalid tungstenite message format"),
+                        tungstenite::Message::Close(_) => return client.span.debug("websocket closed", &[]),
+                        ws_msg => return client.span.warn("invalid tungstenite message format", &[("msg", &format!("{:?}", ws_msg))]),
+                    };
+
+                    let to_game_message: fn(protocol::ClientMessage, usize) -> Option<Message> = match client.spectator {
+                        true => Self::to_spectator_message,
+                        false => Self::to_game_message,
                     };
 
-                    let client_message: Message = match Self::parse_binary(&buf, client.idx) {
-                        Some(client_message) => client_message,
-                        None => return println!("invalid client message binary format"),
+                    let client_message: Message = match protocol::ClientMessage::decode(&buf) {
+                        Ok(protocol_message) => match to_game_message(protocol_message, client.idx) {
+                            Some(client_message) => client_message,
+                            None => return client.span.warn("unexpected client message in a match", &[]),
+                        },
+                        Err(err) => {
+                            metrics::METRICS.decode_errors.inc();
+                            return client.span.warn("invalid client message binary format", &[("err", &err)]);
+                        }
                     };
 
-                    if let Err(err) = client.send_to_game.send(client_message).await { 
-                        return println!("error sending client message: {:#?}", err);
+                    if client.send_to_game.send(client_message).await.is_err() {
+                        return client.span.debug("game closed the channel", &[]);
                     }
 
                 }
@@ -113,25 +292,187 @@ impl Client {
 
     }
 
-    fn parse_binary(buf: &[u8], idx: usize) -> Option<Message> {
-
-        match buf[0] {
-            0 => Some(Message::UpStart(idx)),
-            1 => Some(Message::UpEnd(idx)),
-            2 => Some(Message::DownStart(idx)),
-            3 => Some(Message::DownEnd(idx)),
-            4 => Some(Message::LeftStart(idx)),
-            5 => Some(Message::LeftEnd(idx)),
-            6 => Some(Message::RightStart(idx)),
-            7 => Some(Message::RightEnd(idx)),
-            8 => Some(Message::Click(
-                idx, 
-                u16::from_be_bytes(buf[1..3].try_into().ok()?) as f32, 
-                u16::from_be_bytes(buf[3..5].try_into().ok()?) as f32,
-            )),
+    // the game removes the user (or spectator) right away instead of waiting for a send to fail
+    async fn disconnect(&self) {
+
+        let disconnect_msg: Message = match self.spectator {
+            true => Message::SpectatorDisconnect(self.idx),
+            false => Message::Disconnect(self.idx),
+        };
+
+        // the game may have already ended
+        let _ = self.send_to_game.send(disconnect_msg).await;
+
+    }
+
+    // spectates room_idx if given, otherwise joins as a user, channel_size messages can be waiting to be sent to the client
+    async fn connect(send_to_game: &mpsc::Sender<Message>, spectate_room_idx: Option<usize>, channel_size: usize) -> Option<(usize, mpsc::Receiver<Vec<u8>>, Mailbox)> {
+
+        let (
+            send_to_client, 
+            receive_from_game,
+        ) = mpsc::channel(channel_size);
+
+        let (
+            send_idx_to_client,
+            receive_idx_from_game 
+        ) = oneshot::channel();
+
+        let mailbox: Mailbox = Mailbox::default();
+
+        let connect_msg: Message = match spectate_room_idx {
+            Some(room_idx) => Message::Spectate {
+                room_idx,
+                send_idx_to_client,
+                send_to_client,
+                mailbox: mailbox.clone(),
+            },
+            None => Message::Connect { 
+                send_idx_to_client, 
+                send_to_client, 
+                mailbox: mailbox.clone(),
+            },
+        };
+
+        send_to_game.send(connect_msg).await.ok()?;
+
+        let idx: usize = receive_idx_from_game.await.ok()?;
+
+        Some((idx, receive_from_game, mailbox))
+
+    }
+
+    async fn receive(ws: &mut WebSocket, span: &log::Span) -> Option<protocol::ClientMessage> {
+
+        let buf: Vec<u8> = match ws.next().await {
+            Some(Ok(tungstenite::Message::Binary(buf))) => buf,
+            Some(Ok(tungstenite::Message::Close(_))) | None => {
+                span.debug("websocket closed", &[]);
+                return None;
+            }
+            Some(Ok(ws_msg)) => {
+                span.warn("invalid tungstenite message format", &[("msg", &format!("{:?}", ws_msg))]);
+                return None;
+            }
+            Some(Err(err)) => {
+                span.warn("failed to listen on websocket stream", &[("err", &err)]);
+                return None;
+            }
+        };
+
+        match protocol::ClientMessage::decode(&buf) {
+            Ok(msg) => Some(msg),
+            Err(err) => {
+                metrics::METRICS.decode_errors.inc();
+                span.warn("invalid client message binary format", &[("err", &err)]);
+                None
+            }
+        }
+
+    }
+
+    async fn send(ws: &mut WebSocket, msg: protocol::ServerMessage, span: &log::Span) -> Option<()> {
+
+        match ws.send(tungstenite::Message::binary(msg.encode())).await {
+            Ok(_) => Some(()),
+            Err(err) => {
+                span.warn("failed to send on websocket stream", &[("err", &err)]);
+                None
+            }
+        }
+
+    }
+
+    fn to_game_message(msg: protocol::ClientMessage, idx: usize) -> Option<Message> {
+
+        match msg {
+            protocol::ClientMessage::Input { seq, input: protocol::Input::UpStart } => Some(Message::UpStart(idx, seq)),
+            protocol::ClientMessage::Input { seq, input: protocol::Input::UpEnd } => Some(Message::UpEnd(idx, seq)),
+            protocol::ClientMessage::Input { seq, input: protocol::Input::DownStart } => Some(Message::DownStart(idx, seq)),
+            protocol::ClientMessage::Input { seq, input: protocol::Input::DownEnd } => Some(Message::DownEnd(idx, seq)),
+            protocol::ClientMessage::Input { seq, input: protocol::Input::LeftStart } => Some(Message::LeftStart(idx, seq)),
+            protocol::ClientMessage::Input { seq, input: protocol::Input::LeftEnd } => Some(Message::LeftEnd(idx, seq)),
+            protocol::ClientMessage::Input { seq, input: protocol::Input::RightStart } => Some(Message::RightStart(idx, seq)),
+            protocol::ClientMessage::Input { seq, input: protocol::Input::RightEnd } => Some(Message::RightEnd(idx, seq)),
+            protocol::ClientMessage::Input { seq, input: protocol::Input::Reload } => Some(Message::Reload(idx, seq)),
+            protocol::ClientMessage::Click { seq, view_tick, x, y } => Some(Message::Click(idx, seq, view_tick, x as f32, y as f32)),
+            protocol::ClientMessage::SwitchWeapon { seq, weapon_idx } => Some(Message::SwitchWeapon(idx, seq, weapon_idx as usize)),
+            protocol::ClientMessage::Ack { tick } => Some(Message::Ack(idx, tick)),
+            protocol::ClientMessage::AddBot { difficulty } => Some(Message::AddBot(difficulty)),
+            protocol::ClientMessage::RemoveBot => Some(Message::RemoveBot),
+            _ => None,
+        }
+
+    }
+
+    // spectators can't move or shoot
+    fn to_spectator_message(msg: protocol::ClientMessage, idx: usize) -> Option<Message> {
+
+        match msg {
+            protocol::ClientMessage::WatchRoom { room_idx } => Some(Message::Watch(idx, room_idx as usize)),
+            protocol::ClientMessage::Ack { tick } => Some(Message::SpectatorAck(idx, tick)),
             _ => None,
         }
 
     }
     
-}
\ No newline at end of file
+}
+
+impl Mailbox {
+
+    // sent before the next snapshot
+    pub fn replace_geometry(&self, buf: Vec<u8>) {
+        self.update(|mail| mail.geometry = Some(buf));
+    }
+
+    // true if it replaced a snapshot the client never got to send
+    pub fn replace_snapshot(&self, buf: Vec<u8>) -> bool {
+        self.update(|mail| mail.snapshot.replace(buf).is_some())
+    }
+
+    // the client tells its user why and closes the socket, anything still waiting to be sent is dropped
+    pub fn kick(&self, reason: String) {
+        self.update(|mail| mail.kick_reason = Some(reason));
+    }
+
+    fn update<T>(&self, update: impl FnOnce(&mut Mail) -> T) -> T {
+
+        let (mail, notify) = &*self.0;
+
+        let updated: T = update(&mut mail.lock().unwrap_or_else(std::sync::PoisonError::into_inner));
+
+        notify.notify_one();
+
+        updated
+
+    }
+
+    // waits for mail, a kick goes first and geometry before the snapshot that needs it
+    async fn next(&self) -> Outgoing {
+
+        let (mail, notify) = &*self.0;
+
+        loop {
+
+            let outgoing: Option<Outgoing> = {
+
+                let mut mail = mail.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+
+                match mail.kick_reason.take() {
+                    Some(reason) => Some(Outgoing::Kicked(reason)),
+                    None => mail.geometry.take().or_else(|| mail.snapshot.take()).map(Outgoing::Send),
+                }
+
+            };
+
+            if let Some(outgoing) = outgoing {
+                return outgoing;
+            }
+
+            notify.notified().await;
+
+        }
+
+    }
+
+}
@@ -0,0 +1,220 @@
+use serde::{ Deserialize, Serialize };
+use crate::{ bullet, log, rules, scheduler, simulation, snapshot, user };
+
+// everything about running the server that isn't a room, read from a json file (every field is optional)
+// and then overridden by command line flags, see USAGE
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub addr: String,
+    pub room_dir: std::path::PathBuf,
+    // every match is recorded here when set
+    pub record_dir: Option<std::path::PathBuf>,
+    // users per match, bots included
+    pub max_players: usize,
+    // messages queued for a client before it's too slow to keep up
+    pub channel_size: usize,
+    pub tick: scheduler::Config,
+    // how far back a hitscan shot is resolved against where its shooter saw users, less than snapshot::History::LEN
+    pub max_rewind_ticks: u32,
+    // only physics (and the rooms in room_dir) are reloaded when the files change, the rest needs a restart
+    pub physics: user::Physics,
+    // how matches are played and won, see rules::Rules
+    pub rules: rules::Rules,
+    pub log: log::Config,
+    // serves prometheus metrics over http at /metrics when set
+    pub metrics_addr: Option<String>,
+    // the file this was read from
+    #[serde(skip)]
+    pub path: Option<std::path::PathBuf>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::path::PathBuf, std::io::Error),
+    Parse(std::path::PathBuf, serde_json::Error),
+    UnknownFlag(String),
+    MissingValue(String),
+    InvalidValue { flag: String, value: String },
+    // a field that was read fine but can't be run with
+    Invalid { field: &'static str, expected: &'static str },
+}
+
+pub const USAGE: &str = "usage: server [--config <file>] [--addr <ip:port>] [--room-dir <dir>] [--record <dir>] [--max-players <n>] \
+[--channel-size <n>] [--tick-rate <ticks per second>] [--max-catch-up <ticks>] [--missed-ticks burst|delay|skip] [--max-rewind <ticks>] \
+[--physics <field>=<value>]... [--score-limit <points>|none] [--time-limit <ticks>|none] [--last-hunter-standing true|false] [--log-level error|warn|info|debug|trace] [--log-format human|json] [--metrics-addr <ip:port>] | server replay <file> [tick]";
+
+impl std::fmt::Display for ConfigError {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+
+        match self {
+            ConfigError::Io(path, err) => write!(f, "failed to read {}: {}", path.display(), err),
+            ConfigError::Parse(path, err) => write!(f, "failed to parse {}: {}", path.display(), err),
+            ConfigError::UnknownFlag(flag) => write!(f, "unknown flag {}", flag),
+            ConfigError::MissingValue(flag) => write!(f, "{} needs a value", flag),
+            ConfigError::InvalidValue { flag, value } => write!(f, "invalid value for {}: {}", flag, value),
+            ConfigError::Invalid { field, expected } => write!(f, "{} must be {}", field, expected),
+        }
+
+    }
+
+}
+
+impl Default for ServerConfig {
+
+    fn default() -> Self {
+        Self {
+            addr: String::from("127.0.0.1:3000"),
+            room_dir: std::path::PathBuf::from("rooms"),
+            record_dir: None,
+            max_players: simulation::MAX_PLAYERS,
+            channel_size: 100,
+            tick: scheduler::Config::default(),
+            // ~250ms at the default tick rate
+            max_rewind_ticks: 15,
+            physics: user::Physics::default(),
+            rules: rules::Rules::default(),
+            log: log::Config::default(),
+            metrics_addr: None,
+            path: None,
+        }
+    }
+
+}
+
+impl ServerConfig {
+
+    // the file given with --config (defaults otherwise), then every other flag in order
+    pub fn load(args: &[String]) -> Result<Self, ConfigError> {
+
+        let mut flags: Vec<(&String, &String)> = Vec::with_capacity(args.len() / 2);
+
+        for flag in args.chunks(2) {
+            match flag {
+                [flag, value] if flag.starts_with("--") => flags.push((flag, value)),
+                [flag] if flag.starts_with("--") => return Err(ConfigError::MissingValue(flag.clone())),
+                [arg, ..] => return Err(ConfigError::UnknownFlag(arg.clone())),
+                [] => (),
+            }
+        }
+
+        let mut config: Self = match flags.iter().rev().find(|(flag, _)| *flag == "--config") {
+            Some((_, path)) => Self {
+                path: Some(std::path::PathBuf::from(path)),
+                ..Self::read(std::path::Path::new(path))?
+            },
+            None => Self::default(),
+        };
+
+        for (flag, value) in flags {
+            config.set(flag, value)?;
+        }
+
+        config.validate()?;
+
+        Ok(config)
+
+    }
+
+    fn read(path: &std::path::Path) -> Result<Self, ConfigError> {
+
+        let buf: Vec<u8> = std::fs::read(path).map_err(|err| ConfigError::Io(path.to_path_buf(), err))?;
+
+        serde_json::from_slice(&buf).map_err(|err| ConfigError::Parse(path.to_path_buf(), err))
+
+    }
+
+    fn set(&mut self, flag: &str, value: &str) -> Result<(), ConfigError> {
+
+        let invalid = || ConfigError::InvalidValue { flag: flag.to_string(), value: value.to_string() };
+
+        match flag {
+            // already read
+            "--config" => (),
+            "--addr" => self.addr = value.to_string(),
+            "--room-dir" => self.room_dir = std::path::PathBuf::from(value),
+            "--record" => self.record_dir = Some(std::path::PathBuf::from(value)),
+            "--max-players" => self.max_players = value.parse().map_err(|_| invalid())?,
+            "--channel-size" => self.channel_size = value.parse().map_err(|_| invalid())?,
+            "--tick-rate" => self.tick.tick_rate = value.parse().map_err(|_| invalid())?,
+            "--max-catch-up" => self.tick.max_catch_up_ticks = value.parse().map_err(|_| invalid())?,
+            "--missed-ticks" => self.tick.missed_tick_behavior = scheduler::MissedTickBehavior::from_name(value).ok_or_else(invalid)?,
+            "--score-limit" => self.rules.score_limit = parse_limit(value).ok_or_else(invalid)?,
+            "--time-limit" => self.rules.time_limit_ticks = parse_limit(value).ok_or_else(invalid)?,
+            "--last-hunter-standing" => self.rules.last_hunter_standing = value.parse().map_err(|_| invalid())?,
+            "--max-rewind" => self.max_rewind_ticks = value.parse().map_err(|_| invalid())?,
+            "--log-level" => self.log.level = log::Level::from_name(value).ok_or_else(invalid)?,
+            "--log-format" => self.log.format = log::Format::from_name(value).ok_or_else(invalid)?,
+            "--metrics-addr" => self.metrics_addr = Some(value.to_string()),
+            // field=value, the value is parsed like it would be in the file
+            "--physics" => {
+
+                let (field, field_value) = value.split_once('=').ok_or_else(invalid)?;
+
+                let mut physics: serde_json::Value = serde_json::to_value(self.physics).map_err(|_| invalid())?;
+
+                *physics.get_mut(field).ok_or_else(invalid)? = serde_json::from_str(field_value).map_err(|_| invalid())?;
+
+                self.physics = serde_json::from_value(physics).map_err(|_| invalid())?;
+
+            }
+            _ => return Err(ConfigError::UnknownFlag(flag.to_string())),
+        }
+
+        Ok(())
+
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+
+        if self.addr.parse::<std::net::SocketAddr>().is_err() {
+            return Err(ConfigError::Invalid { field: "addr", expected: "an ip address and port" });
+        }
+
+        if self.metrics_addr.as_ref().is_some_and(|metrics_addr| metrics_addr.parse::<std::net::SocketAddr>().is_err()) {
+            return Err(ConfigError::Invalid { field: "metrics_addr", expected: "an ip address and port" });
+        }
+
+        if self.max_players == 0 || self.max_players > simulation::MAX_PLAYERS {
+            return Err(ConfigError::Invalid { field: "max_players", expected: "between 1 and 255" });
+        }
+
+        if self.channel_size == 0 {
+            return Err(ConfigError::Invalid { field: "channel_size", expected: "above 0" });
+        }
+
+        if self.tick.tick_rate == 0 || self.tick.tick_rate > 1000 {
+            return Err(ConfigError::Invalid { field: "tick.tick_rate", expected: "between 1 and 1000" });
+        }
+
+        if self.tick.max_catch_up_ticks == 0 {
+            return Err(ConfigError::Invalid { field: "tick.max_catch_up_ticks", expected: "above 0" });
+        }
+
+        // older ticks aren't in the history to rewind to
+        if self.max_rewind_ticks as usize >= snapshot::History::<bullet::Hitboxes>::LEN {
+            return Err(ConfigError::Invalid { field: "max_rewind_ticks", expected: "below 32" });
+        }
+
+        if let Some((field, expected)) = self.physics.invalid_field() {
+            return Err(ConfigError::Invalid { field, expected });
+        }
+
+        if let Some((field, expected)) = self.rules.invalid_field() {
+            return Err(ConfigError::Invalid { field, expected });
+        }
+
+        Ok(())
+
+    }
+
+}
+
+// a number, or none for no limit
+fn parse_limit<T: std::str::FromStr>(value: &str) -> Option<Option<T>> {
+    match value {
+        "none" => Some(None),
+        value => value.parse().ok().map(Some),
+    }
+}
@@ -1,9 +1,13 @@
-use crate::room;
+use serde::{ Deserialize, Serialize };
+use crate::{ protocol, room };
 
 pub enum CollisionVariant<'a> {
     Bounds,
     User(&'a Entity),
     Platform(&'a Entity),
+    Solid(&'a Entity),
+    // (moving platform idx, where it is this tick)
+    MovingPlatform(usize, &'a Entity),
     Door(&'a room::Door),
 }
 
@@ -22,16 +26,18 @@ pub enum VerticalCollisionDirection {
 pub struct HorizontalCollision<'a> {
     pub variant: CollisionVariant<'a>, 
     pub direction: HorizontalCollisionDirection, 
+    #[allow(dead_code)]
     pub time: f32,
 }
 
 pub struct VerticalCollision<'a> {
     pub variant: CollisionVariant<'a>,
     pub direction: VerticalCollisionDirection, 
+    #[allow(dead_code)]
     pub time: f32,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Entity {
     pub x: f32,
     pub y: f32,
@@ -39,6 +45,26 @@ pub struct Entity {
     pub height: f32,
 }
 
+impl Entity {
+
+    pub fn overlaps(&self, other: &Entity) -> bool {
+        self.x < other.x + other.width &&
+        other.x < self.x + self.width &&
+        self.y < other.y + other.height &&
+        other.y < self.y + self.height
+    }
+
+    pub fn to_rect(&self) -> protocol::Rect {
+        protocol::Rect {
+            width: self.width as u8,
+            height: self.height as u8,
+            x: self.x as u16,
+            y: self.y as u16,
+        }
+    }
+
+}
+
 #[derive(Debug)]
 pub struct DynamicEntity {
     pub entity: Entity,
@@ -49,7 +75,7 @@ pub struct DynamicEntity {
 
 impl DynamicEntity {
 
-    pub fn horizontal_bounds_collision(&self, bounds: &'static room::Bounds) -> Option<HorizontalCollision<'static>> {
+    pub fn horizontal_bounds_collision<'a>(&self, bounds: &'a room::Bounds) -> Option<HorizontalCollision<'a>> {
 
         match self.dx.partial_cmp(&0.0) {
             Some(std::cmp::Ordering::Greater) => {
@@ -69,7 +95,7 @@ impl DynamicEntity {
             }
             Some(std::cmp::Ordering::Less) => {
 
-                let bounds_collision: bool = self.entity.x <= self.dx * -1.0;
+                let bounds_collision: bool = self.entity.x <= -self.dx;
 
                 if bounds_collision {
                     return Some(HorizontalCollision {
@@ -87,7 +113,7 @@ impl DynamicEntity {
 
     }
 
-    pub fn vertical_bounds_collision(&self, bounds: &'static room::Bounds) -> Option<VerticalCollision<'static>> {
+    pub fn vertical_bounds_collision<'a>(&self, bounds: &'a room::Bounds) -> Option<VerticalCollision<'a>> {
 
         match self.dy.partial_cmp(&0.0) {
             Some(std::cmp::Ordering::Greater) => {
@@ -107,7 +133,7 @@ impl DynamicEntity {
             }
             Some(std::cmp::Ordering::Less) => {
 
-                let bounds_collision: bool = self.entity.y <= self.dy * -1.0;
+                let bounds_collision: bool = self.entity.y <= -self.dy;
 
                 if bounds_collision {
                     return Some(VerticalCollision {
@@ -205,7 +231,7 @@ impl DynamicEntity {
 
     // }
 
-    pub fn swept_collision<'a, 'b>(&'a self, other: &'b Entity) -> (f32, Option<HorizontalCollisionDirection>, Option<VerticalCollisionDirection>) {
+    pub fn swept_collision(&self, other: &Entity) -> (f32, Option<HorizontalCollisionDirection>, Option<VerticalCollisionDirection>) {
 
         let (x_entry_time, x_exit_time) = if self.dx == 0.0 {
             
@@ -289,7 +315,7 @@ impl DynamicEntity {
 
         let entry_time: f32 = f32::max(x_entry_time, y_entry_time);
 
-        if entry_time < 0.0 || entry_time > 1.0 {
+        if !(0.0..=1.0).contains(&entry_time) {
             return (
                 entry_time,
                 None,
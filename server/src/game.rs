@@ -1,292 +1,890 @@
-use tokio::sync::mpsc;
-use crate::{ bullet, client, entity, ray, room, slice, user };
-use slice::IterPlucked;
+use std::sync::Arc;
+use tokio::sync::{ mpsc, watch };
+use crate::{ bot, bullet, client, config, entity, kinematic, lobby, log, metrics, nav, projectile, protocol, replay, room, rules, scheduler, simulation, snapshot, user };
 
 pub struct Game {
+    match_id: u32,
     receive_from_client: mpsc::Receiver<client::Message>,
-    users: Vec<Option<user::User>>,
-    rooms_mut: room::RoomsMut,
+    // from the lobby when the room or config files change
+    reloads: watch::Receiver<lobby::Reload>,
+    send_to_lobby: mpsc::Sender<lobby::Message>,
+    simulation: simulation::Simulation,
+    // indexed like simulation.users
+    connections: Vec<Option<Connection>>,
+    spectators: Vec<Option<Spectator>>,
+    bots: Vec<bot::Bot>,
+    // bots aim with it too, so the same match gets the same bots
+    seed: u64,
+    nav: nav::NavGraph,
+    inputs: Vec<simulation::Input>,
+    user_states: Vec<snapshot::History<Vec<protocol::UserState>>>,
     rooms_to_render: Vec<usize>,
+    recorder: Option<replay::Recorder>,
+    span: log::Span,
+    // users per room last added to the metrics, taken back out when the match is dropped
+    room_users: Vec<i64>,
+    // bytes queued for clients by the current tick
+    sent_bytes: usize,
 }
 
-pub const TICK_DT: u64 = 16;
-pub const MAX_PLAYERS: usize = u8::MAX as usize;
+pub struct Connection {
+    // everything but geometry and snapshots
+    pub send_to_client: mpsc::Sender<Vec<u8>>,
+    pub mailbox: client::Mailbox,
+    pub last_input_seq: u32,
+    pub geometry_room_idx: Option<usize>,
+    pub acked_tick: Option<u32>,
+    // tick of the first snapshot sent with the current geometry, acks of earlier ones (from before a reload) are ignored
+    pub geometry_tick: u32,
+    // the client is sent a snapshot every send_interval ticks, more while it can't keep up
+    pub send_interval: u32,
+    // snapshots in a row replaced before they were sent, and sent before being replaced
+    pub missed_snapshots: u32,
+    pub kept_up_snapshots: u32,
+}
+
+// what became of a snapshot (and geometry) handed to a client
+enum Sent {
+    // bytes put in the client's mailbox, and whether they replaced a snapshot the client never got to
+    Queued { bytes: usize, replaced: bool },
+    Closed,
+}
+
+pub struct Spectator {
+    pub room_idx: usize,
+    // tick the spectator started watching room_idx, earlier acks belong to the previous room
+    pub room_entry_tick: u32,
+    pub connection: Connection,
+    // users knocked out of a last hunter standing match keep watching over their own connection
+    pub eliminated_user_idx: Option<usize>,
+    // kicked or its channel closed, nothing more is sent to it but its idx stays taken until the client's own
+    // disconnect arrives, a spectator joining into it meanwhile would be disconnected in its place
+    pub closed: bool,
+}
+
+pub const MAX_SPECTATORS: usize = 32;
+pub const MAX_BOTS: usize = 16;
+// snapshots in a row a client can miss before it's sent them half as often, or kicked once that's every MAX_SEND_INTERVAL ticks
+pub const MAX_MISSED_SNAPSHOTS: u32 = 10;
+pub const MAX_SEND_INTERVAL: u32 = 8;
+// snapshots in a row a slowed down client has to be sent to get them twice as often again
+pub const CATCH_UP_SNAPSHOTS: u32 = 60;
 
 impl Game {
 
-    pub async fn init(receive_from_client: mpsc::Receiver<client::Message>) {
+    pub async fn init(match_id: u32, receive_from_client: mpsc::Receiver<client::Message>, mut reloads: watch::Receiver<lobby::Reload>, rules: rules::Rules, config: Arc<config::ServerConfig>, seed: u64, send_to_lobby: mpsc::Sender<lobby::Message>) {
+
+        let span: log::Span = log::Span::root("match").with("match_id", match_id);
+
+        span.info("match started", &[("seed", &seed)]);
+
+        // config.physics is only what the server started with
+        let lobby::Reload { rooms, physics } = reloads.borrow_and_update().clone();
+
+        let recorder: Option<replay::Recorder> = config.record_dir.as_ref().and_then(|record_dir| {
+
+            let started: u64 = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+
+            match replay::Recorder::create(record_dir.join(format!("{}-{}.replay", match_id, started)), &rooms, &rules, &physics, config.max_rewind_ticks, seed) {
+                Ok(recorder) => Some(recorder),
+                Err(err) => {
+                    span.error("failed to record match", &[("err", &err)]);
+                    None
+                }
+            }
+
+        });
 
         let mut game: Self = Self {
+            match_id,
             receive_from_client,
-            users: Vec::with_capacity(MAX_PLAYERS),
-            rooms_mut: room::rooms_mut(),
-            rooms_to_render: Vec::with_capacity(room::ROOM_COUNT), 
+            reloads,
+            send_to_lobby,
+            connections: Vec::with_capacity(config.max_players),
+            spectators: Vec::new(),
+            bots: Vec::new(),
+            seed,
+            nav: nav::NavGraph::build(&rooms, &physics),
+            inputs: Vec::new(),
+            user_states: (0..rooms.len()).map(|_| snapshot::History::new()).collect(),
+            rooms_to_render: Vec::with_capacity(rooms.len()),
+            recorder,
+            span,
+            room_users: Vec::new(),
+            sent_bytes: 0,
+            simulation: simulation::Simulation::new(rooms, rules, physics, seed),
         };
 
-        let mut timer: tokio::time::Interval = tokio::time::interval(tokio::time::Duration::from_millis(TICK_DT));
+        game.simulation.max_players = config.max_players;
+        game.simulation.max_rewind_ticks = config.max_rewind_ticks;
+
+        let mut scheduler: scheduler::Scheduler = scheduler::Scheduler::new(config.tick);
 
         loop {
             tokio::select! {
-                
+
+                // ticks go first so a burst of messages can't hold them up
+                biased;
+
+                _ = tokio::time::sleep_until(scheduler.next_deadline()) => {
+
+                    for tick in scheduler.due() {
+
+                        let started: tokio::time::Instant = tokio::time::Instant::now();
+
+                        game.tick();
+
+                        scheduler.record(tick, started.elapsed());
+
+                        if game.simulation.phase == rules::Phase::Ended {
+                            break;
+                        }
+
+                    }
+
+                    if let Some(tick_stats) = scheduler.take_stats() {
+                        game.broadcast(tick_stats);
+                    }
+
+                },
+
+                Ok(()) = game.reloads.changed() => {
+
+                    let reload: lobby::Reload = game.reloads.borrow_and_update().clone();
+
+                    game.hot_reload(reload.rooms, reload.physics);
+
+                },
+
                 client_msg = game.receive_from_client.recv() => {
 
                     let client_msg: client::Message = match client_msg {
                         Some(msg) => msg,
-                        None => return println!("no client message found"),
+                        None => return game.span.error("every sender of the match's channel is gone", &[]),
                     };
 
                     game.handle_client_msg(client_msg);
 
                 },
 
-                _ = timer.tick() => game.tick(),
+            }
 
+            if game.is_empty() || game.simulation.phase == rules::Phase::Ended {
+                break;
             }
+
         }
 
+        // the lobby can still hand out this game's channel until it processes this message, those clients will fail to connect
+        if let Err(err) = game.send_to_lobby.send(lobby::Message::Ended(game.match_id)).await {
+            return game.span.error("failed to tell the lobby the match ended", &[("err", &err)]);
+        }
+
+        game.span.info("match ended", &[("tick", &game.simulation.tick), ("winner_user_idx", &game.simulation.winner().map_or(String::from("none"), |user_idx| user_idx.to_string()))]);
+
+    }
+
+    // a match is only torn down once someone has joined and every client has left, bots don't keep it alive
+    fn is_empty(&self) -> bool {
+        !self.simulation.users.is_empty()
+            && self.connections.iter().all(|connection| connection.is_none())
+            && self.spectators.iter().flatten().all(|spectator| spectator.eliminated_user_idx.is_none())
     }
 
     fn handle_client_msg(&mut self, client_msg: client::Message) {
 
-        match client_msg {
-            client::Message::Connect { send_idx_to_client, send_to_client } => {
+        let (idx, seq, input) = match client_msg {
+            client::Message::Connect { send_idx_to_client, send_to_client, mailbox } => {
 
-                match self.users.iter().position(|user| user.is_none()) {
-                    Some(idx) => {
-                        
-                        let target_user_idx: usize = user::User::get_target_idx(&mut self.users, idx);
-                        
-                        if send_idx_to_client.send(idx).is_ok() {
-                            self.users[idx] = Some(user::User::new(idx as u8, 0, target_user_idx,  send_to_client));
-                        }
+                // dropping send_idx_to_client tells the client the match is full
+                let idx: usize = match self.simulation.join() {
+                    Some(idx) => idx,
+                    None => return,
+                };
 
-                    }
-                    None => {
-                        
-                        let idx: usize = self.users.len();
-                        
-                        let target_user_idx: usize = user::User::get_target_idx(&mut self.users, idx);
-                        
-                        if send_idx_to_client.send(idx).is_ok() {
-                            self.users.push(Some(user::User::new(idx as u8, 0, target_user_idx, send_to_client)));
-                        }
+                self.record(replay::Event::Join(idx));
 
-                    }
+                if send_idx_to_client.send(idx).is_err() {
+                    return self.leave(idx);
+                }
+
+                if idx >= self.connections.len() {
+                    self.connections.resize_with(idx + 1, || None);
+                }
+
+                let connection: Connection = Connection::new(send_to_client, mailbox);
+
+                // the client was sent its idx, so it will still disconnect
+                if connection.send_to_client.try_send(self.match_phase().encode()).is_err() {
+                    return self.drop_client(idx);
                 }
 
+                self.connections[idx] = Some(connection);
+
+                return;
+
             },
-            client::Message::UpStart(idx) => { self.users[idx].as_mut().map(|user| user.jump_buffer_ticks = user::User::JUMP_BUFFER_TICKS); },
-            client::Message::UpEnd(idx) => { self.users[idx].as_mut().map(|user| user.end_jump()); },
-            client::Message::DownStart(idx) => { self.users[idx].as_mut().map(|user| user.holding_down = true); }
-            client::Message::DownEnd(idx) => { self.users[idx].as_mut().map(|user| user.holding_down = false); }
-            client::Message::LeftStart(idx) => { self.users[idx].as_mut().map(|user| user.holding_left = true); },
-            client::Message::LeftEnd(idx) => { self.users[idx].as_mut().map(|user| user.holding_left = false); },
-            client::Message::RightStart(idx) => { self.users[idx].as_mut().map(|user| user.holding_right = true); },
-            client::Message::RightEnd(idx) => { self.users[idx].as_mut().map(|user| user.holding_right = false); },
-            client::Message::Click(idx, x, y) => { 
-                
-                let user: &user::User = match self.users[idx].as_ref() {
-                    Some(user) => user,
+            client::Message::Spectate { room_idx, send_idx_to_client, send_to_client, mailbox } => {
+
+                // dropping send_idx_to_client tells the client the room doesn't exist or there are too many spectators
+                if room_idx >= self.simulation.rooms.len() {
+                    return;
+                }
+
+                let idx: usize = match self.spectators.iter().position(|spectator| spectator.is_none()) {
+                    Some(idx) => idx,
+                    None if self.spectators.len() < MAX_SPECTATORS => {
+                        self.spectators.push(None);
+                        self.spectators.len() - 1
+                    }
                     None => return,
                 };
 
-                self.rooms_mut[user.room_idx].bullets.push(bullet::Bullet {
-                    user_idx: idx,
-                    target_user_idx: user.target_user_idx,
-                    room_idx: user.room_idx,
-                    ray: ray::Ray::from_entity_and_position(&user.dynamic_entity.entity, x, y),
-                }); 
+                if send_idx_to_client.send(idx).is_err() {
+                    return;
+                }
+
+                self.spectators[idx] = Some(Spectator {
+                    room_idx,
+                    room_entry_tick: self.simulation.tick,
+                    connection: Connection::new(send_to_client, mailbox),
+                    eliminated_user_idx: None,
+                    closed: false,
+                });
+
+                return;
+
+            },
+            client::Message::Ack(idx, tick) => {
+
+                if let Some(connection) = self.connections[idx].as_mut() {
+                    connection.ack(tick);
+                } else if let Some(spectator_idx) = self.eliminated_spectator_idx(idx) {
+                    self.spectators[spectator_idx].as_mut().unwrap().connection.ack(tick); // found above
+                }
+
+                return;
+
+            },
+            client::Message::Disconnect(idx) => {
+
+                if self.simulation.reserved.contains(&idx) {
+                    self.simulation.release(idx);
+                    return self.record(replay::Event::Release(idx));
+                }
+
+                if let Some(spectator_idx) = self.eliminated_spectator_idx(idx) {
+                    self.spectators[spectator_idx] = None;
+                }
+
+                return self.leave(idx);
+
+            },
+            client::Message::AddBot(level) => return self.add_bot(bot::Difficulty::from_level(level)),
+            client::Message::RemoveBot => {
+
+                if let Some(bot) = self.bots.last() {
+                    self.leave(bot.user_idx);
+                }
+
+                return;
+
+            },
+            client::Message::SpectatorDisconnect(idx) => {
+                self.spectators[idx] = None;
+                return;
+            },
+            client::Message::SpectatorAck(idx, tick) => {
+
+                if let Some(spectator) = self.spectators[idx].as_mut() {
+                    spectator.connection.ack(tick);
+                }
+
+                return;
+
+            },
+            client::Message::Watch(idx, room_idx) => {
+
+                if room_idx >= self.simulation.rooms.len() {
+                    return self.span.warn("spectator can't watch a room that doesn't exist", &[("spectator_idx", &idx), ("room_idx", &room_idx)]);
+                }
+
+                if let Some(spectator) = self.spectators[idx].as_mut() {
+                    spectator.room_idx = room_idx;
+                    spectator.room_entry_tick = self.simulation.tick;
+                }
+
+                return;
 
+            },
+            client::Message::UpStart(idx, seq) => (idx, seq, simulation::Input::UpStart(idx)),
+            client::Message::UpEnd(idx, seq) => (idx, seq, simulation::Input::UpEnd(idx)),
+            client::Message::DownStart(idx, seq) => (idx, seq, simulation::Input::DownStart(idx)),
+            client::Message::DownEnd(idx, seq) => (idx, seq, simulation::Input::DownEnd(idx)),
+            client::Message::LeftStart(idx, seq) => (idx, seq, simulation::Input::LeftStart(idx)),
+            client::Message::LeftEnd(idx, seq) => (idx, seq, simulation::Input::LeftEnd(idx)),
+            client::Message::RightStart(idx, seq) => (idx, seq, simulation::Input::RightStart(idx)),
+            client::Message::RightEnd(idx, seq) => (idx, seq, simulation::Input::RightEnd(idx)),
+            client::Message::Click(idx, seq, view_tick, x, y) => (idx, seq, simulation::Input::Click(idx, view_tick, x, y)),
+            client::Message::Reload(idx, seq) => (idx, seq, simulation::Input::Reload(idx)),
+            client::Message::SwitchWeapon(idx, seq, weapon_idx) => (idx, seq, simulation::Input::SwitchWeapon(idx, weapon_idx)),
+        };
+
+        // inputs are applied at the start of the next tick, so that tick's snapshot reports seq as processed
+        let controlled: bool = match self.connections.get_mut(idx).and_then(|connection| connection.as_mut()) {
+            Some(connection) => {
+                connection.last_input_seq = seq;
+                true
             }
+            None => self.bots.iter().any(|bot| bot.user_idx == idx),
         };
 
-    }
+        if controlled {
+            self.inputs.push(input);
+            self.record(replay::Event::Input(input));
+        }
 
-    fn tick(&mut self) {
+    }
 
-        let len: usize = self.users.len();
+    // happens between ticks, every client is sent its room's new geometry with the next snapshot
+    fn hot_reload(&mut self, rooms: Arc<Vec<room::Room>>, physics: user::Physics) {
 
-        if len == 0 { 
-            return;
+        // users, spectators and doors all point at rooms by idx
+        if rooms.len() != self.simulation.rooms.len() {
+            return self.span.warn("match keeps its rooms, only new matches are played in the reloaded ones", &[("rooms", &self.simulation.rooms.len()), ("reloaded_rooms", &rooms.len())]);
         }
 
-        for room_mut in &mut self.rooms_mut {
-                                    
-            for bullet in &room_mut.bullets {
-                room_mut.bullet_paths.push(bullet.tick(&mut self.users));
-            }
-            
-            room_mut.bullets.clear();
-            
+        self.record(replay::Event::HotReload(rooms.clone(), physics));
+
+        self.nav = nav::NavGraph::build(&rooms, &physics);
+
+        let moved: Vec<usize> = self.simulation.hot_reload(rooms, physics);
+
+        self.span.info("reloaded rooms and physics", &[]);
+
+        for user_idx in moved {
+            self.user_span(user_idx).info("moved user out of the reloaded geometry", &[]);
         }
 
-        for idx in 0..self.users.len() {
+        let connections = self.connections
+            .iter_mut()
+            .flatten()
+            .chain(self.spectators.iter_mut().flatten().map(|spectator| &mut spectator.connection));
 
-            if self.users[idx].is_none() {
-                continue;
-            }
+        for connection in connections {
+            connection.geometry_room_idx = None;
+        }
 
-            let (plucked, iter) = self.users.iter_plucked(idx).unwrap(); // none len = 0 (can't happen if in a loop)
-            let user: &mut user::User = plucked.as_mut().unwrap();
-            let users_iter = iter.filter_map(|u| u.as_ref());
+    }
 
-            self.rooms_to_render.push(user.room_idx);
+    // the match's span with the user and the room they're in, if they're in one
+    fn user_span(&self, user_idx: usize) -> log::Span {
 
-            user.tick(users_iter);
+        let span: log::Span = self.span.child("user").with("user_idx", user_idx);
 
+        match self.simulation.users.get(user_idx).and_then(|user| user.as_ref()) {
+            Some(user) => span.with("room_idx", user.room_idx),
+            None => span,
         }
 
-        for idx in 0..self.rooms_to_render.len() {
-            let room_idx: usize = self.rooms_to_render[idx]; // indexing to avoid dealing with additional pointer indirection 
-            let buf: Vec<u8> = self.render_room(room_idx);
-            self.send_render_buffer(room_idx, buf);
+    }
+
+    fn spectator_span(&self, spectator_idx: usize) -> log::Span {
+
+        let span: log::Span = self.span.child("spectator").with("spectator_idx", spectator_idx);
+
+        match self.spectators.get(spectator_idx).and_then(|spectator| spectator.as_ref()) {
+            Some(spectator) => span.with("room_idx", spectator.room_idx),
+            None => span,
         }
 
-        self.rooms_to_render.clear();
+    }
 
-        for room_mut in &mut self.rooms_mut {
-            room_mut.bullet_paths.clear();
+    fn eliminated_spectator_idx(&self, user_idx: usize) -> Option<usize> {
+        self.spectators
+            .iter()
+            .position(|spectator| spectator.as_ref().is_some_and(|spectator| spectator.eliminated_user_idx == Some(user_idx)))
+    }
+
+    fn add_bot(&mut self, difficulty: bot::Difficulty) {
+
+        if self.bots.len() >= MAX_BOTS {
+            return self.span.warn("match already has the most bots it can", &[("bots", &MAX_BOTS)]);
         }
 
+        let idx: usize = match self.simulation.join() {
+            Some(idx) => idx,
+            None => return,
+        };
+
+        self.record(replay::Event::Join(idx));
+
+        self.user_span(idx).info("bot joined", &[("reaction_ticks", &difficulty.reaction_ticks), ("aim_error", &difficulty.aim_error)]);
+
+        self.bots.push(bot::Bot::new(idx, difficulty, self.seed));
+
     }
 
-    fn render_room(&self, room_idx: usize) -> Vec<u8> {
+    fn record(&mut self, event: replay::Event) {
 
-        let mut buf: Vec<u8> = Vec::new();
+        if let Some(recorder) = self.recorder.as_mut() {
+            if let Err(err) = recorder.record(self.simulation.tick, event) {
+                self.span.error("stopped recording match", &[("err", &err)]);
+                self.recorder = None;
+            }
+        }
 
-        let room: &room::Room = &room::ROOMS[room_idx];
-        let room_mut: &room::RoomMut = &self.rooms_mut[room_idx];
+    }
+
+    // called for both explicit disconnects and closed channels, whichever is noticed first
+    fn leave(&mut self, idx: usize) {
 
-        for entity in room.platforms
-        {
-            
-            buf.push(1);
-            buf.push(2);
+        if !self.simulation.is_playing(idx) {
+            return;
+        }
 
-            buf.push(entity.width as u8);
-            buf.push(entity.height as u8);
+        self.simulation.leave(idx);
 
-            buf.extend_from_slice(&(entity.x as u16).to_be_bytes());
-            buf.extend_from_slice(&(entity.y as u16).to_be_bytes());
+        self.record(replay::Event::Leave(idx));
 
+        if let Some(connection) = self.connections.get_mut(idx) {
+            *connection = None;
         }
 
-        for entity in room.doors.iter().map(|door| &door.entity) {
+        self.bots.retain(|bot| bot.user_idx != idx);
+
+    }
+
+    // a kicked client or one whose channel closed is out of the match, but still sends its disconnect and maybe
+    // some inputs before it, so its idx isn't given to anyone until then
+    fn drop_client(&mut self, idx: usize) {
+
+        self.leave(idx);
+
+        self.simulation.reserve(idx);
+
+        self.record(replay::Event::Reserve(idx));
+
+    }
+
+    fn tick(&mut self) {
 
-            buf.push(3);
-            buf.push(4);
+        if self.simulation.users.is_empty() {
+            return;
+        }
 
-            buf.push(entity.width as u8);
-            buf.push(entity.height as u8);
+        let bot_msgs: Vec<client::Message> = self.bots
+            .iter_mut()
+            .flat_map(|bot| bot.think(&self.simulation, &self.nav))
+            .collect();
+
+        for bot_msg in bot_msgs {
+            self.handle_client_msg(bot_msg);
+        }
 
-            buf.extend_from_slice(&(entity.x as u16).to_be_bytes());
-            buf.extend_from_slice(&(entity.y as u16).to_be_bytes());
+        self.simulation.tick(self.inputs.drain(..));
 
+        if self.recorder.is_some() {
+            self.record(replay::Event::Checksum(replay::checksum(&self.simulation)));
         }
 
-        for path in &room_mut.bullet_paths {
+        for event in std::mem::take(&mut self.simulation.events) {
+            match event {
+                simulation::Event::Phase => {
+
+                    self.broadcast(self.match_phase());
+
+                    if self.simulation.phase == rules::Phase::Ended {
+                        self.broadcast(self.scoreboard());
+                    }
+
+                }
+                simulation::Event::Eliminated { user_idx, room_idx } => {
+                    metrics::METRICS.kills.inc();
+                    self.eliminate(user_idx, room_idx);
+                }
+                simulation::Event::Killed { user_idx, respawn_tick } => {
 
-            buf.push(4);
-            buf.push(5);
+                    metrics::METRICS.kills.inc();
 
-            buf.extend_from_slice(&(path.origin_x as u16).to_be_bytes());
-            buf.extend_from_slice(&(path.origin_y as u16).to_be_bytes());
+                    // a closed channel is noticed by the next snapshot
+                    if let Some(connection) = self.connections.get(user_idx).and_then(|connection| connection.as_ref()) {
+                        let _ = connection.send_to_client.try_send(protocol::ServerMessage::Killed { respawn_tick }.encode());
+                    }
 
-            buf.extend_from_slice(&(path.end_x as u16).to_be_bytes());
-            buf.extend_from_slice(&(path.end_y as u16).to_be_bytes());
+                }
+                simulation::Event::Fired { .. } => metrics::METRICS.shots_fired.inc(),
+                simulation::Event::Hit { .. } => metrics::METRICS.hits.inc(),
+            }
+        }
 
+        for user in self.simulation.users.iter().filter_map(|user| user.as_ref()) {
+            if !self.rooms_to_render.contains(&user.room_idx) {
+                self.rooms_to_render.push(user.room_idx);
+            }
         }
 
-        for user in self.users
-            .iter()
-            .filter_map(|user| user.as_ref())
-            .filter(|user| user.room_idx == room_idx)
-        {
-                        
-            buf.push(0);
-            buf.push(0);
-            buf.push(0);
-            buf.push(user.idx);
+        for respawn in &self.simulation.respawns {
+            if !self.rooms_to_render.contains(&respawn.room_idx) {
+                self.rooms_to_render.push(respawn.room_idx);
+            }
+        }
 
-            let entity: &entity::Entity = &user.dynamic_entity.entity;
+        for spectator in self.spectators.iter().filter_map(|spectator| spectator.as_ref()) {
+            if !self.rooms_to_render.contains(&spectator.room_idx) {
+                self.rooms_to_render.push(spectator.room_idx);
+            }
+        }
 
-            buf.push(entity.width as u8);
-            buf.push(entity.height as u8);
+        for idx in 0..self.rooms_to_render.len() {
+            let room_idx: usize = self.rooms_to_render[idx]; // indexing to avoid dealing with additional pointer indirection
+            self.record_room(room_idx);
+            self.send_snapshots(room_idx);
+        }
 
-            buf.extend_from_slice(&(entity.x as u16).to_be_bytes());
-            buf.extend_from_slice(&(entity.y as u16).to_be_bytes());
+        self.rooms_to_render.clear();
+
+        metrics::METRICS.tick_sent_bytes.observe(std::mem::take(&mut self.sent_bytes) as f64);
 
+        let mut room_users: Vec<i64> = vec![0; self.simulation.rooms.len()];
+
+        for user in self.simulation.users.iter().flatten() {
+            room_users[user.room_idx] += 1;
         }
 
-        return buf;
+        if room_users != self.room_users {
+            metrics::METRICS.update_room_users(&mut self.room_users, &room_users);
+        }
 
     }
 
-    fn send_render_buffer(&mut self, room_idx: usize, mut buf: Vec<u8>) {
+    // the user is already gone from the simulation, their client stays on as a spectator of the room they died in
+    fn eliminate(&mut self, user_idx: usize, room_idx: usize) {
 
-        let mut last_idx: Option<usize> = None;
+        self.bots.retain(|bot| bot.user_idx != user_idx);
 
-        for idx in (0..self.users.len()).rev() {
+        let connection: Connection = match self.connections.get_mut(user_idx).and_then(|connection| connection.take()) {
+            Some(connection) => connection,
+            None => return,
+        };
 
-            let user_room_idx: usize = match &self.users[idx] {
-                Some(user) => user.room_idx,
-                None => continue,
-            };
+        if connection.send_to_client.try_send(protocol::ServerMessage::Spectating { match_id: self.match_id }.encode()).is_err() {
+            return;
+        }
 
-            if user_room_idx != room_idx {
-                continue;
+        // eliminated users are let in past MAX_SPECTATORS, they were already in the match
+        let spectator: Spectator = Spectator {
+            room_idx,
+            room_entry_tick: self.simulation.tick,
+            connection,
+            eliminated_user_idx: Some(user_idx),
+            closed: false,
+        };
+
+        match self.spectators.iter().position(|spectator| spectator.is_none()) {
+            Some(idx) => self.spectators[idx] = Some(spectator),
+            None => self.spectators.push(Some(spectator)),
+        }
+
+    }
+
+    // sent to every user and spectator, closed channels are noticed by the next snapshot
+    fn broadcast(&self, msg: protocol::ServerMessage) {
+
+        let buf: Vec<u8> = msg.encode();
+
+        for (idx, connection) in self.connections.iter().enumerate() {
+            if let Some(Err(mpsc::error::TrySendError::Full(_))) = connection.as_ref().map(|connection| connection.send_to_client.try_send(buf.clone())) {
+                metrics::METRICS.dropped_frames.inc();
+                self.user_span(idx).warn("failed to send to a client, its channel is full", &[]);
+            }
+        }
+
+        for (idx, spectator) in self.spectators.iter().enumerate() {
+            if let Some(Err(mpsc::error::TrySendError::Full(_))) = spectator.as_ref().filter(|spectator| !spectator.closed).map(|spectator| spectator.connection.send_to_client.try_send(buf.clone())) {
+                metrics::METRICS.dropped_frames.inc();
+                self.spectator_span(idx).warn("failed to send to a client, its channel is full", &[]);
             }
+        }
 
-            last_idx = Some(idx);
-            break;
+    }
 
+    fn match_phase(&self) -> protocol::ServerMessage {
+        protocol::ServerMessage::MatchPhase {
+            phase: self.simulation.phase.to_u8(),
+            end_tick: self.simulation.phase_end_tick,
         }
+    }
 
-        let last_idx: usize = match last_idx {
-            Some(idx) => idx,
-            None => return,
+    fn scoreboard(&self) -> protocol::ServerMessage {
+        protocol::ServerMessage::Scoreboard {
+            winner_user_idx: self.simulation.winner().map(|user_idx| user_idx as u8),
+            scores: self.simulation.scores
+                .iter()
+                .enumerate()
+                .filter_map(|(user_idx, score)| score.map(|score| score.to_protocol(user_idx)))
+                .collect(),
+        }
+    }
+
+    fn record_room(&mut self, room_idx: usize) {
+        self.user_states[room_idx].push(self.simulation.tick, self.simulation.user_states(room_idx));
+    }
+
+    fn room_geometry(&self, room_idx: usize) -> Vec<u8> {
+
+        let room: &room::Room = &self.simulation.rooms[room_idx];
+
+        protocol::ServerMessage::RoomGeometry(protocol::RoomGeometry {
+            room_idx: room_idx as u16,
+            platforms: room.platforms.iter().map(entity::Entity::to_rect).collect(),
+            doors: room.doors.iter().map(|door| door.entity.to_rect()).collect(),
+            walls: room.walls.iter().map(entity::Entity::to_rect).collect(),
+            solids: room.solids.iter().map(entity::Entity::to_rect).collect(),
+        }).encode()
+
+    }
+
+    fn encode_snapshot(&self, room_idx: usize, baseline_tick: Option<u32>) -> Vec<u8> {
+
+        let user_states: &snapshot::History<Vec<protocol::UserState>> = &self.user_states[room_idx];
+
+        let current: &Vec<protocol::UserState> = user_states.latest().unwrap(); // recorded before sending
+
+        let (users, removed_users) = match baseline_tick.and_then(|tick| user_states.get(tick)) {
+            Some(baseline) => snapshot::delta(baseline, current),
+            None => (current.clone(), Vec::new()),
         };
 
-        for idx in 0..last_idx {
+        let mut buf: Vec<u8> = Vec::new();
+
+        protocol::Snapshot {
+            tick: self.simulation.tick,
+            baseline_tick,
+            users,
+            removed_users,
+            bullet_paths: self.simulation.rooms_mut[room_idx].bullet_paths.iter().map(bullet::BulletPath::to_line).collect(),
+            projectiles: self.simulation.rooms_mut[room_idx].projectiles.iter().map(projectile::Projectile::to_point).collect(),
+            moving_platforms: self.simulation.rooms_mut[room_idx].moving_platforms.iter().map(kinematic::State::to_rect).collect(),
+        }.encode(&mut buf);
+
+        buf
+
+    }
+
+    fn send_snapshots(&mut self, room_idx: usize) {
+
+        // most users in a room ack the same few ticks, so each baseline is only encoded once
+        let mut encoded: Vec<(Option<u32>, Vec<u8>)> = Vec::new();
+
+        for idx in 0..self.connections.len() {
+
+            // killed users watch the room they died in without a footer until they respawn
+            let (user, room_entry_tick): (Option<&user::User>, u32) = match (&self.simulation.users[idx], self.simulation.respawn(idx)) {
+                (Some(user), _) if user.room_idx == room_idx => (Some(user), user.room_entry_tick),
+                (None, Some(respawn)) if respawn.room_idx == room_idx => (None, respawn.killed_tick),
+                _ => continue,
+            };
 
-            let user: &user::User = match &self.users[idx] {
-                Some(user) => user,
+            // taken out while sending so the rest of the game can still be read
+            let mut connection: Connection = match self.connections[idx].take() {
+                Some(connection) => connection,
                 None => continue,
             };
 
-            if user.room_idx != room_idx {
+            if !self.simulation.tick.is_multiple_of(connection.send_interval) {
+                self.connections[idx] = Some(connection);
                 continue;
             }
 
-            let mut buf: Vec<u8> = buf.clone();
+            let footer: Option<protocol::Footer> = user.map(|user| protocol::Footer {
+                user_idx: user.idx,
+                target_user_idx: user.target_user_idx as u8,
+                last_input_seq: connection.last_input_seq,
+                movement: user.movement(),
+                loadout: user.loadout.to_protocol(),
+                target_reveal: self.simulation.users[user.target_user_idx]
+                    .as_ref()
+                    .filter(|target| target.reveal_ticks > 0)
+                    .map(|target| protocol::Reveal { room_idx: target.room_idx as u16, rect: target.dynamic_entity.entity.to_rect() }),
+            });
+
+            match self.send_snapshot(&mut connection, room_idx, room_entry_tick, footer, &mut encoded) {
+                Sent::Queued { bytes, replaced } => {
+
+                    self.sent_bytes += bytes;
+
+                    if connection.keep_up(replaced, || self.user_span(idx)) {
+                        self.connections[idx] = Some(connection);
+                    } else {
+                        self.drop_client(idx);
+                    }
+
+                }
+                Sent::Closed => self.drop_client(idx),
+            }
+
+        }
+
+        for idx in 0..self.spectators.len() {
+
+            let mut spectator: Spectator = match self.spectators[idx].take() {
+                Some(spectator) if !spectator.closed && spectator.room_idx == room_idx && self.simulation.tick.is_multiple_of(spectator.connection.send_interval) => spectator,
+                spectator => {
+                    self.spectators[idx] = spectator;
+                    continue;
+                }
+            };
+
+            match self.send_snapshot(&mut spectator.connection, room_idx, spectator.room_entry_tick, None, &mut encoded) {
+                Sent::Queued { bytes, replaced } => {
 
-            // footer
-            buf.push(user.idx);
-            buf.push(user.target_user_idx as u8);
+                    self.sent_bytes += bytes;
+
+                    if !spectator.connection.keep_up(replaced, || self.spectator_span(idx).with("room_idx", room_idx)) {
+                        spectator.closed = true;
+                    }
 
-            match user.send_to_client.try_send(buf) {
-                Ok(_) => (),
-                Err(mpsc::error::TrySendError::Closed(_)) => self.users[idx] = None,
-                Err(err) => return println!("failed to send render buffer: {:#?}", err),
+                }
+                Sent::Closed => spectator.closed = true,
             }
 
+            self.spectators[idx] = Some(spectator);
+
         }
 
-        let last_user: &user::User = match &self.users[last_idx] {
-            Some(user) => user,
-            None => return,
+    }
+
+    // spectators and killed users are sent the snapshot without a footer
+    fn send_snapshot(&self, connection: &mut Connection, room_idx: usize, room_entry_tick: u32, footer: Option<protocol::Footer>, encoded: &mut Vec<(Option<u32>, Vec<u8>)>) -> Sent {
+
+        if connection.send_to_client.is_closed() {
+            return Sent::Closed;
+        }
+
+        let mut bytes: usize = 0;
+
+        if connection.geometry_room_idx != Some(room_idx) {
+
+            let geometry: Vec<u8> = self.room_geometry(room_idx);
+
+            bytes += geometry.len();
+
+            connection.mailbox.replace_geometry(geometry);
+
+            // acks from the previous room refer to another room's history
+            connection.geometry_room_idx = Some(room_idx);
+            connection.geometry_tick = self.simulation.tick;
+            connection.acked_tick = None;
+
+        }
+
+        let baseline_tick: Option<u32> = connection.acked_tick
+            .filter(|tick| *tick >= room_entry_tick)
+            .filter(|tick| self.user_states[room_idx].get(*tick).is_some());
+
+        let body_idx: usize = match encoded.iter().position(|(tick, _)| *tick == baseline_tick) {
+            Some(body_idx) => body_idx,
+            None => {
+                encoded.push((baseline_tick, self.encode_snapshot(room_idx, baseline_tick)));
+                encoded.len() - 1
+            }
         };
 
-        // footer
-        buf.push(last_user.idx);
-        buf.push(last_user.target_user_idx as u8);
+        let mut buf: Vec<u8> = encoded[body_idx].1.clone();
+
+        match footer {
+            Some(footer) => footer.encode(&mut buf),
+            None => buf[0] = protocol::ServerMessage::SPECTATOR_SNAPSHOT_TAG,
+        }
+
+        bytes += buf.len();
+
+        Sent::Queued { bytes, replaced: connection.mailbox.replace_snapshot(buf) }
 
-        match last_user.send_to_client.try_send(buf) {
-            Ok(_) => (),
-            Err(mpsc::error::TrySendError::Closed(_)) => self.users[last_idx] = None,
-            Err(err) => return println!("failed to send render buffer: {:#?}", err),
+    }
+
+}
+
+impl Drop for Game {
+
+    // however the match ends its users leave the rooms
+    fn drop(&mut self) {
+        metrics::METRICS.update_room_users(&mut self.room_users, &[]);
+    }
+
+}
+
+impl Connection {
+
+    fn new(send_to_client: mpsc::Sender<Vec<u8>>, mailbox: client::Mailbox) -> Self {
+        Self {
+            send_to_client,
+            mailbox,
+            last_input_seq: 0,
+            geometry_room_idx: None,
+            acked_tick: None,
+            geometry_tick: 0,
+            send_interval: 1,
+            missed_snapshots: 0,
+            kept_up_snapshots: 0,
+        }
+    }
+
+    // acks can arrive out of order, only ever move the baseline forward
+    fn ack(&mut self, tick: u32) {
+
+        if tick < self.geometry_tick {
+            return;
+        }
+
+        if self.acked_tick.is_none_or(|acked_tick| tick > acked_tick) {
+            self.acked_tick = Some(tick);
+        }
+
+    }
+
+    // counts whether the client was sent the previous snapshot before this one replaced it, a client that keeps
+    // missing them is sent them less often and then kicked, false once it's kicked
+    fn keep_up(&mut self, missed: bool, span: impl FnOnce() -> log::Span) -> bool {
+
+        if !missed {
+
+            self.missed_snapshots = 0;
+            self.kept_up_snapshots += 1;
+
+            if self.send_interval > 1 && self.kept_up_snapshots >= CATCH_UP_SNAPSHOTS {
+                self.send_interval /= 2;
+                self.kept_up_snapshots = 0;
+                span().info("client caught up, sending it more snapshots", &[("send_interval", &self.send_interval)]);
+            }
+
+            return true;
+
+        }
+
+        metrics::METRICS.dropped_frames.inc();
+
+        self.kept_up_snapshots = 0;
+        self.missed_snapshots += 1;
+
+        if self.missed_snapshots < MAX_MISSED_SNAPSHOTS {
+            return true;
+        }
+
+        if self.send_interval >= MAX_SEND_INTERVAL {
+            span().warn("kicked a client that can't keep up", &[("missed_snapshots", &self.missed_snapshots)]);
+            self.mailbox.kick(String::from("your connection is too slow to keep up with the match"));
+            return false;
         }
 
+        self.send_interval *= 2;
+        self.missed_snapshots = 0;
+
+        span().warn("client can't keep up, sending it fewer snapshots", &[("send_interval", &self.send_interval)]);
+
+        true
+
     }
 
-}
\ No newline at end of file
+}
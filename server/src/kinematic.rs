@@ -0,0 +1,229 @@
+use serde::{ Deserialize, Serialize };
+use crate::{ entity, protocol };
+
+// a platform that moves along a path on its own, where it is only depends on the tick so it never drifts
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MovingPlatform {
+    pub width: f32,
+    pub height: f32,
+    pub path: Path,
+}
+
+// positions are the platform's top left corner
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Path {
+    // back and forth between two points, taking ticks each way
+    Linear { from: Point, to: Point, ticks: u32 },
+    // around a circle, taking ticks per lap
+    Circular { center: Point, radius: f32, ticks: u32 },
+    // from one point to the next at speed (pixels per tick), then back to the first
+    Waypoints { points: Vec<Point>, speed: f32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Point {
+    pub x: f32,
+    pub y: f32,
+}
+
+// where a moving platform is this tick and how far it moved to get there, kept in room::RoomMut
+#[derive(Debug, Clone)]
+pub struct State {
+    pub entity: entity::Entity,
+    pub dx: f32,
+    pub dy: f32,
+}
+
+impl MovingPlatform {
+
+    pub fn entity_at(&self, tick: u32) -> entity::Entity {
+
+        let point: Point = self.path.point_at(tick);
+
+        entity::Entity {
+            x: point.x,
+            y: point.y,
+            width: self.width,
+            height: self.height,
+        }
+
+    }
+
+    pub fn state_at(&self, tick: u32) -> State {
+
+        let entity: entity::Entity = self.entity_at(tick);
+        // it hasn't moved yet on the first tick
+        let previous: Point = self.path.point_at(tick.saturating_sub(1));
+
+        State {
+            dx: entity.x - previous.x,
+            dy: entity.y - previous.y,
+            entity,
+        }
+
+    }
+
+    // the space the platform sweeps over its whole path
+    pub fn extent(&self) -> entity::Entity {
+
+        let points: Vec<Point> = match &self.path {
+            Path::Linear { from, to, .. } => vec![*from, *to],
+            Path::Circular { center, radius, .. } => vec![
+                Point { x: center.x - radius, y: center.y - radius },
+                Point { x: center.x + radius, y: center.y + radius },
+            ],
+            Path::Waypoints { points, .. } => points.clone(),
+        };
+
+        let x_min: f32 = points.iter().map(|point| point.x).fold(f32::INFINITY, f32::min);
+        let y_min: f32 = points.iter().map(|point| point.y).fold(f32::INFINITY, f32::min);
+        let x_max: f32 = points.iter().map(|point| point.x).fold(f32::NEG_INFINITY, f32::max);
+        let y_max: f32 = points.iter().map(|point| point.y).fold(f32::NEG_INFINITY, f32::max);
+
+        entity::Entity {
+            x: x_min,
+            y: y_min,
+            width: x_max - x_min + self.width,
+            height: y_max - y_min + self.height,
+        }
+
+    }
+
+}
+
+impl Path {
+
+    // a path that never finishes a lap can't be followed
+    pub fn is_valid(&self) -> bool {
+        match self {
+            Path::Linear { ticks, .. } | Path::Circular { ticks, .. } => *ticks > 0,
+            Path::Waypoints { points, speed } => !points.is_empty() && *speed > 0.0,
+        }
+    }
+
+    fn point_at(&self, tick: u32) -> Point {
+
+        match self {
+            Path::Linear { from, to, ticks } => {
+
+                // there and back, u64 so a lap of more than u32::MAX ticks doesn't overflow
+                let ticks: u64 = *ticks as u64;
+                let lap_tick: u64 = tick as u64 % (ticks * 2);
+
+                let progress: f32 = match lap_tick < ticks {
+                    true => lap_tick as f32 / ticks as f32,
+                    false => (ticks * 2 - lap_tick) as f32 / ticks as f32,
+                };
+
+                lerp(*from, *to, progress)
+
+            }
+            Path::Circular { center, radius, ticks } => {
+
+                let angle: f32 = std::f32::consts::TAU * (tick % ticks) as f32 / *ticks as f32;
+
+                Point {
+                    x: center.x + radius * angle.cos(),
+                    y: center.y + radius * angle.sin(),
+                }
+
+            }
+            Path::Waypoints { points, speed } => {
+
+                let lengths: Vec<f32> = (0..points.len()).map(|idx| distance(points[idx], points[(idx + 1) % points.len()])).collect();
+                let lap: f32 = lengths.iter().sum();
+
+                if lap == 0.0 {
+                    return points[0];
+                }
+
+                // f64 so the distance travelled stays precise over long matches
+                let mut travelled: f32 = ((tick as f64 * *speed as f64) % lap as f64) as f32;
+
+                for (idx, length) in lengths.iter().enumerate() {
+
+                    if travelled < *length {
+                        return lerp(points[idx], points[(idx + 1) % points.len()], travelled / length);
+                    }
+
+                    travelled -= length;
+
+                }
+
+                points[0]
+
+            }
+        }
+
+    }
+
+}
+
+impl State {
+
+    pub fn to_rect(&self) -> protocol::Rect {
+        self.entity.to_rect()
+    }
+
+}
+
+fn lerp(from: Point, to: Point, progress: f32) -> Point {
+    Point {
+        x: from.x + (to.x - from.x) * progress,
+        y: from.y + (to.y - from.y) * progress,
+    }
+}
+
+fn distance(a: Point, b: Point) -> f32 {
+    ((a.x - b.x).powi(2) + (a.y - b.y).powi(2)).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn linear() -> MovingPlatform {
+        MovingPlatform {
+            width: 30.0,
+            height: 3.0,
+            path: Path::Linear { from: Point { x: 10.0, y: 50.0 }, to: Point { x: 40.0, y: 20.0 }, ticks: 30 },
+        }
+    }
+
+    #[test]
+    fn first_tick_has_not_moved() {
+
+        let state: State = linear().state_at(0);
+
+        assert_eq!((state.entity.x, state.entity.y), (10.0, 50.0));
+        assert_eq!((state.dx, state.dy), (0.0, 0.0));
+
+    }
+
+    #[test]
+    fn moves_there_and_back() {
+
+        let platform: MovingPlatform = linear();
+
+        let there: State = platform.state_at(1);
+        let turned: State = platform.state_at(30);
+        let back: State = platform.state_at(31);
+
+        assert_eq!((there.dx, there.dy), (1.0, -1.0));
+        assert_eq!((turned.entity.x, turned.entity.y), (40.0, 20.0));
+        assert_eq!((back.dx, back.dy), (-1.0, 1.0));
+        assert_eq!(platform.state_at(60).entity.x, 10.0);
+
+    }
+
+    #[test]
+    fn extent_covers_the_whole_path() {
+
+        let extent: entity::Entity = linear().extent();
+
+        assert_eq!((extent.x, extent.y, extent.width, extent.height), (10.0, 20.0, 60.0, 33.0));
+
+    }
+
+}
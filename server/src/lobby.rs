@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{ mpsc, oneshot, watch };
+use crate::{ client, config, game, log, room, simulation, user };
+
+#[derive(Debug)]
+pub enum Message {
+    List {
+        send_list_to_client: oneshot::Sender<Vec<u32>>,
+    },
+    Create {
+        send_match_to_client: oneshot::Sender<Option<Match>>,
+    },
+    Join {
+        match_id: u32,
+        send_match_to_client: oneshot::Sender<Option<Match>>,
+    },
+    Ended(u32),
+    // running matches switch to these between ticks, new ones start with them
+    HotReload { rooms: Arc<Vec<room::Room>>, physics: user::Physics },
+}
+
+// the rooms and physics matches are played with, each match only switches to the latest one it hasn't yet
+#[derive(Debug, Clone)]
+pub struct Reload {
+    pub rooms: Arc<Vec<room::Room>>,
+    pub physics: user::Physics,
+}
+
+#[derive(Debug)]
+pub struct Match {
+    pub match_id: u32,
+    pub send_to_game: mpsc::Sender<client::Message>,
+}
+
+pub struct Lobby {
+    receive_from_client: mpsc::Receiver<Message>,
+    send_to_lobby: mpsc::Sender<Message>,
+    games: HashMap<u32, mpsc::Sender<client::Message>>,
+    next_match_id: u32,
+    // new matches start with the latest reload, a match busy ticking picks it up whenever it gets to it
+    reloads: watch::Sender<Reload>,
+    config: Arc<config::ServerConfig>,
+}
+
+pub const MAX_MATCHES: usize = 64;
+
+impl Lobby {
+
+    pub async fn init(receive_from_client: mpsc::Receiver<Message>, send_to_lobby: mpsc::Sender<Message>, rooms: Arc<Vec<room::Room>>, config: Arc<config::ServerConfig>) {
+
+        let mut lobby: Self = Self {
+            receive_from_client,
+            send_to_lobby,
+            games: HashMap::with_capacity(MAX_MATCHES),
+            next_match_id: 0,
+            reloads: watch::Sender::new(Reload { rooms, physics: config.physics }),
+            config,
+        };
+
+        while let Some(msg) = lobby.receive_from_client.recv().await {
+            lobby.handle_msg(msg);
+        }
+
+        log::Span::root("lobby").error("every sender of the lobby's channel is gone", &[]);
+
+    }
+
+    fn handle_msg(&mut self, msg: Message) {
+
+        match msg {
+            Message::List { send_list_to_client } => {
+
+                let mut match_ids: Vec<u32> = self.games.keys().copied().collect();
+
+                match_ids.sort_unstable();
+
+                let _ = send_list_to_client.send(match_ids);
+
+            }
+            Message::Create { send_match_to_client } => {
+                let _ = send_match_to_client.send(self.create());
+            }
+            Message::Join { match_id, send_match_to_client } => {
+
+                let found_match: Option<Match> = self.games
+                    .get(&match_id)
+                    .map(|send_to_game| Match { match_id, send_to_game: send_to_game.clone() });
+
+                let _ = send_match_to_client.send(found_match);
+
+            }
+            Message::Ended(match_id) => {
+                self.games.remove(&match_id);
+            }
+            Message::HotReload { rooms, physics } => {
+
+                self.reloads.send_replace(Reload { rooms, physics });
+
+            }
+        }
+
+    }
+
+    fn create(&mut self) -> Option<Match> {
+
+        if self.games.len() >= MAX_MATCHES {
+            return None;
+        }
+
+        let match_id: u32 = self.next_match_id;
+
+        self.next_match_id = self.next_match_id.wrapping_add(1);
+
+        let (
+            send_to_game,
+            receive_from_client,
+        ) = mpsc::channel(simulation::MAX_PLAYERS);
+
+        tokio::spawn(game::Game::init(
+            match_id,
+            receive_from_client,
+            self.reloads.subscribe(),
+            self.config.rules.clone(),
+            self.config.clone(),
+            rand::random(),
+            self.send_to_lobby.clone(),
+        ));
+
+        self.games.insert(match_id, send_to_game.clone());
+
+        Some(Match { match_id, send_to_game })
+
+    }
+
+}
@@ -0,0 +1,265 @@
+use serde::{ Deserialize, Serialize };
+
+// leveled log lines on stderr, each carrying the fields of the span it was logged in (which match, connection, user..),
+// spans are plain values handed down to whoever logs in them
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    // lines below this level are dropped
+    pub level: Level,
+    pub format: Format,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    // `<time> <LEVEL> <span>{<field>=<value> ..} ..: <message> <field>=<value> ..`, spans without fields have no braces
+    Human,
+    // one object per line with time, level, message, spans (outermost first) and fields
+    Json,
+}
+
+// a named scope and its fields, children keep their parents' fields
+#[derive(Debug, Clone, Default)]
+pub struct Span {
+    scopes: Vec<(&'static str, Vec<(&'static str, String)>)>,
+}
+
+pub type Fields<'a> = &'a [(&'static str, &'a dyn std::fmt::Display)];
+
+static CONFIG: std::sync::OnceLock<Config> = std::sync::OnceLock::new();
+
+impl Default for Config {
+
+    fn default() -> Self {
+        Self {
+            level: Level::Info,
+            format: Format::Human,
+        }
+    }
+
+}
+
+impl Level {
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "error" => Some(Level::Error),
+            "warn" => Some(Level::Warn),
+            "info" => Some(Level::Info),
+            "debug" => Some(Level::Debug),
+            "trace" => Some(Level::Trace),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+
+}
+
+impl Format {
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "human" => Some(Format::Human),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+
+}
+
+// lines logged before this use the default config, only the first call counts
+pub fn init(config: Config) {
+    let _ = CONFIG.set(config);
+}
+
+impl Span {
+
+    pub fn root(name: &'static str) -> Self {
+        Self {
+            scopes: vec![(name, Vec::new())],
+        }
+    }
+
+    pub fn child(&self, name: &'static str) -> Self {
+
+        let mut span: Self = self.clone();
+
+        span.scopes.push((name, Vec::new()));
+
+        span
+
+    }
+
+    // adds a field to the innermost scope, replacing one with the same key
+    pub fn with(mut self, key: &'static str, value: impl std::fmt::Display) -> Self {
+        self.record(key, value);
+        self
+    }
+
+    pub fn record(&mut self, key: &'static str, value: impl std::fmt::Display) {
+
+        let fields: &mut Vec<(&'static str, String)> = match self.scopes.last_mut() {
+            Some((_, fields)) => fields,
+            None => return,
+        };
+
+        match fields.iter_mut().find(|(field_key, _)| *field_key == key) {
+            Some((_, field_value)) => *field_value = value.to_string(),
+            None => fields.push((key, value.to_string())),
+        }
+
+    }
+
+    pub fn error(&self, msg: &str, fields: Fields) {
+        self.log(Level::Error, msg, fields);
+    }
+
+    pub fn warn(&self, msg: &str, fields: Fields) {
+        self.log(Level::Warn, msg, fields);
+    }
+
+    pub fn info(&self, msg: &str, fields: Fields) {
+        self.log(Level::Info, msg, fields);
+    }
+
+    pub fn debug(&self, msg: &str, fields: Fields) {
+        self.log(Level::Debug, msg, fields);
+    }
+
+    pub fn log(&self, level: Level, msg: &str, fields: Fields) {
+
+        let config: &Config = CONFIG.get_or_init(Config::default);
+
+        if level > config.level {
+            return;
+        }
+
+        let line: String = match config.format {
+            Format::Human => self.human(level, msg, fields),
+            Format::Json => self.json(level, msg, fields),
+        };
+
+        eprintln!("{}", line);
+
+    }
+
+    fn human(&self, level: Level, msg: &str, fields: Fields) -> String {
+
+        let mut line: String = format!("{} {:>5}", timestamp(), level.name().to_uppercase());
+
+        for (name, scope_fields) in &self.scopes {
+
+            line.push(' ');
+            line.push_str(name);
+
+            if scope_fields.is_empty() {
+                continue;
+            }
+
+            let scope_fields: Vec<String> = scope_fields.iter().map(|(key, value)| format!("{}={}", key, value)).collect();
+
+            line.push_str(&format!("{{{}}}", scope_fields.join(" ")));
+
+        }
+
+        line.push_str(": ");
+        line.push_str(msg);
+
+        for (key, value) in fields {
+            line.push_str(&format!(" {}={}", key, value));
+        }
+
+        line
+
+    }
+
+    fn json(&self, level: Level, msg: &str, fields: Fields) -> String {
+
+        let spans: Vec<serde_json::Value> = self.scopes
+            .iter()
+            .map(|(name, scope_fields)| {
+
+                let mut span: serde_json::Map<String, serde_json::Value> = serde_json::Map::new();
+
+                span.insert(String::from("name"), serde_json::Value::from(*name));
+
+                for (key, value) in scope_fields {
+                    span.insert(key.to_string(), serde_json::Value::from(value.as_str()));
+                }
+
+                serde_json::Value::Object(span)
+
+            })
+            .collect();
+
+        let mut line: serde_json::Map<String, serde_json::Value> = serde_json::Map::new();
+
+        line.insert(String::from("time"), serde_json::Value::from(timestamp()));
+        line.insert(String::from("level"), serde_json::Value::from(level.name()));
+        line.insert(String::from("msg"), serde_json::Value::from(msg));
+        line.insert(String::from("spans"), serde_json::Value::from(spans));
+
+        let fields: serde_json::Map<String, serde_json::Value> = fields
+            .iter()
+            .map(|(key, value)| (key.to_string(), serde_json::Value::from(value.to_string())))
+            .collect();
+
+        line.insert(String::from("fields"), serde_json::Value::Object(fields));
+
+        serde_json::Value::Object(line).to_string()
+
+    }
+
+}
+
+// utc, rfc 3339 with milliseconds
+fn timestamp() -> String {
+
+    let elapsed: std::time::Duration = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+
+    let secs: u64 = elapsed.as_secs();
+    let (year, month, day) = civil_date((secs / 86400) as i64);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year, month, day, secs % 86400 / 3600, secs % 3600 / 60, secs % 60, elapsed.subsec_millis(),
+    )
+
+}
+
+// (year, month, day) of days since 1970-01-01, Howard Hinnant's days_from_civil inverted
+fn civil_date(days: i64) -> (i64, u32, u32) {
+
+    let days: i64 = days + 719468;
+    let era: i64 = days.div_euclid(146097);
+    let day_of_era: i64 = days.rem_euclid(146097);
+    let year_of_era: i64 = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year: i64 = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month: i64 = (5 * day_of_year + 2) / 153;
+    let day: u32 = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month: u32 = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+    let year: i64 = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+
+}
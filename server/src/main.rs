@@ -1,33 +1,117 @@
-use tokio;
+use std::sync::Arc;
 use tokio::sync::mpsc;
 
+mod bot;
 mod bullet;
 mod client;
+mod config;
 mod entity;
 mod game;
+mod kinematic;
+mod lobby;
+mod log;
+mod metrics;
+mod nav;
+mod projectile;
+#[allow(dead_code)] // the client half of the protocol is for bots and tools
+mod protocol;
 mod ray;
+mod replay;
 mod room;
+mod rules;
+mod scheduler;
+mod simulation;
 mod slice;
+mod snapshot;
+mod spawn;
 mod user;
-
-const ADDR: &'static str = "127.0.0.1:3000";
+mod watch;
+mod weapon;
 
 #[tokio::main]
 async fn main() {
 
-    let listener: tokio::net::TcpListener = tokio::net::TcpListener::bind(&ADDR).await.expect("tcp listeniner bind");
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    // server replay <file> [tick]
+    if args.first().is_some_and(|arg| arg == "replay") {
+        return replay_tool(&args[1..]);
+    }
+
+    // server [--config <file>] [--<flag> <value>]...
+    let config: config::ServerConfig = match config::ServerConfig::load(&args) {
+        Ok(config) => config,
+        Err(err) => return println!("invalid config: {}\n{}", err, config::USAGE),
+    };
+
+    log::init(config.log);
+
+    let span: log::Span = log::Span::root("server");
+
+    let rooms: Vec<room::Room> = match room::load_rooms(&config.room_dir) {
+        Ok(rooms) => rooms,
+        Err(err) => return span.error("failed to load rooms", &[("room_dir", &config.room_dir.display()), ("err", &err)]),
+    };
+
+    let listener: tokio::net::TcpListener = match tokio::net::TcpListener::bind(&config.addr).await {
+        Ok(listener) => listener,
+        Err(err) => return span.error("failed to listen", &[("addr", &config.addr), ("err", &err)]),
+    };
+
+    span.info("listening", &[("addr", &config.addr), ("rooms", &rooms.len()), ("tick_rate", &config.tick.tick_rate)]);
+
+    if let Some(metrics_addr) = &config.metrics_addr {
+
+        let metrics_listener: tokio::net::TcpListener = match tokio::net::TcpListener::bind(metrics_addr).await {
+            Ok(metrics_listener) => metrics_listener,
+            Err(err) => return span.error("failed to listen for metrics", &[("addr", metrics_addr), ("err", &err)]),
+        };
 
-    println!("Listening on:\n{}\n", ADDR);
+        span.info("serving metrics", &[("addr", metrics_addr)]);
+
+        tokio::spawn(metrics::serve(metrics_listener, log::Span::root("metrics")));
+
+    }
 
     let (
-        send_to_game, 
+        send_to_lobby, 
         receive_from_client
-    ) = mpsc::channel(game::MAX_PLAYERS);
+    ) = mpsc::channel(simulation::MAX_PLAYERS);
+
+    let channel_size: usize = config.channel_size;
+    let config: Arc<config::ServerConfig> = Arc::new(config);
+
+    tokio::spawn(lobby::Lobby::init(receive_from_client, send_to_lobby.clone(), Arc::new(rooms), config.clone()));
+    tokio::spawn(watch::watch(args, config, send_to_lobby.clone()));
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                tokio::spawn(client::Client::init(stream, send_to_lobby.clone(), channel_size, log::Span::root("connection").with("peer", peer)));
+            }
+            Err(err) => return span.error("failed to accept a connection", &[("err", &err)]),
+        }
+    }
+
+}
+
+// prints what the replay shows, anything that goes wrong is logged
+fn replay_tool(args: &[String]) {
+
+    let span: log::Span = log::Span::root("replay");
 
-    tokio::spawn(game::Game::init(receive_from_client));
+    let (path, show_tick) = match args {
+        [path] => (path, None),
+        [path, tick] => match tick.parse() {
+            Ok(tick) => (path, Some(tick)),
+            Err(_) => return span.error("tick must be a number", &[("tick", tick)]),
+        },
+        _ => return span.error("usage: server replay <file> [tick]", &[]),
+    };
 
-    while let Ok((stream, _)) = listener.accept().await {
-        tokio::spawn(client::Client::init(stream, send_to_game.clone()));
+    match replay::play(std::path::Path::new(path), show_tick) {
+        Ok(playback) => println!("replayed {} ticks, {} of {} render checksums differ", playback.ticks, playback.mismatches, playback.checksums),
+        Err(err) => span.error("failed to replay", &[("path", path), ("err", &err)]),
     }
 
-}
\ No newline at end of file
+}
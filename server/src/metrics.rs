@@ -0,0 +1,289 @@
+use std::sync::atomic::{ AtomicI64, AtomicU64, Ordering };
+use tokio::io::{ AsyncReadExt, AsyncWriteExt };
+use crate::log;
+
+// counters shared by every match and connection, served in the prometheus text format from `/metrics` when
+// metrics_addr is set, every update is a relaxed atomic so nothing waits on a scrape
+pub struct Metrics {
+    pub connected_users: Gauge,
+    pub connected_spectators: Gauge,
+    // users (bots included) in each room summed over every match, indexed by room idx
+    room_users: std::sync::Mutex<Vec<i64>>,
+    pub tick_seconds: Histogram<10>,
+    // ticks that took longer than the time between ticks
+    pub tick_overruns: Counter,
+    pub ticks_skipped: Counter,
+    // bytes of geometry and snapshots a match queued for its clients in one tick
+    pub tick_sent_bytes: Histogram<8>,
+    // snapshots replaced before a client was sent them and messages dropped because its channel was full
+    pub dropped_frames: Counter,
+    pub shots_fired: Counter,
+    pub hits: Counter,
+    pub kills: Counter,
+    pub decode_errors: Counter,
+}
+
+pub struct Counter(AtomicU64);
+
+pub struct Gauge(AtomicI64);
+
+pub struct Histogram<const N: usize> {
+    // upper bounds of every bucket but +Inf
+    bounds: [f64; N],
+    // observations per bucket, not cumulative
+    buckets: [AtomicU64; N],
+    count: AtomicU64,
+    // in units of 1/scale so it can be added to atomically, millionths for seconds and whole bytes for bytes so
+    // neither wraps in any realistic uptime
+    sum: AtomicU64,
+    scale: f64,
+}
+
+pub static METRICS: Metrics = Metrics {
+    connected_users: Gauge::new(),
+    connected_spectators: Gauge::new(),
+    room_users: std::sync::Mutex::new(Vec::new()),
+    tick_seconds: Histogram::new([0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1], 1_000_000.0),
+    tick_overruns: Counter::new(),
+    ticks_skipped: Counter::new(),
+    tick_sent_bytes: Histogram::new([256.0, 1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0, 4194304.0], 1.0),
+    dropped_frames: Counter::new(),
+    shots_fired: Counter::new(),
+    hits: Counter::new(),
+    kills: Counter::new(),
+    decode_errors: Counter::new(),
+};
+
+// a request line longer than this is answered with a 400
+const MAX_REQUEST_BYTES: usize = 4096;
+const REQUEST_TIMEOUT_MS: u64 = 5000;
+
+impl Counter {
+
+    const fn new() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+}
+
+impl Gauge {
+
+    const fn new() -> Self {
+        Self(AtomicI64::new(0))
+    }
+
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec(&self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+}
+
+impl<const N: usize> Histogram<N> {
+
+    const fn new(bounds: [f64; N], scale: f64) -> Self {
+        Self {
+            bounds,
+            buckets: [const { AtomicU64::new(0) }; N],
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+            scale,
+        }
+    }
+
+    pub fn observe(&self, value: f64) {
+
+        if let Some(bucket_idx) = self.bounds.iter().position(|bound| value <= *bound) {
+            self.buckets[bucket_idx].fetch_add(1, Ordering::Relaxed);
+        }
+
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add((value * self.scale) as u64, Ordering::Relaxed);
+
+    }
+
+    fn write(&self, out: &mut String, name: &str, help: &str) {
+
+        header(out, name, help, "histogram");
+
+        let mut cumulative: u64 = 0;
+
+        for (bound, bucket) in self.bounds.iter().zip(&self.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            out.push_str(&format!("{}_bucket{{le=\"{}\"}} {}\n", name, bound, cumulative));
+        }
+
+        // read last so +Inf is never below a bucket being observed into at the same time
+        let count: u64 = self.count.load(Ordering::Relaxed).max(cumulative);
+
+        out.push_str(&format!("{}_bucket{{le=\"+Inf\"}} {}\n", name, count));
+        out.push_str(&format!("{}_sum {}\n", name, self.sum.load(Ordering::Relaxed) as f64 / self.scale));
+        out.push_str(&format!("{}_count {}\n", name, count));
+
+    }
+
+}
+
+impl Metrics {
+
+    // adds the difference between a match's new and previously reported users per room
+    pub fn update_room_users(&self, reported: &mut Vec<i64>, room_users: &[i64]) {
+
+        let mut totals = match self.room_users.lock() {
+            Ok(totals) => totals,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        let len: usize = reported.len().max(room_users.len());
+
+        reported.resize(len, 0);
+
+        if totals.len() < len {
+            totals.resize(len, 0);
+        }
+
+        for room_idx in 0..len {
+            let users: i64 = room_users.get(room_idx).copied().unwrap_or(0);
+            totals[room_idx] += users - reported[room_idx];
+            reported[room_idx] = users;
+        }
+
+    }
+
+    // the text exposition format, version 0.0.4
+    pub fn render(&self) -> String {
+
+        let mut out: String = String::new();
+
+        header(&mut out, "server_connected_clients", "Websocket clients in a match, by what they joined as.", "gauge");
+        out.push_str(&format!("server_connected_clients{{joined_as=\"user\"}} {}\n", self.connected_users.get()));
+        out.push_str(&format!("server_connected_clients{{joined_as=\"spectator\"}} {}\n", self.connected_spectators.get()));
+
+        header(&mut out, "server_room_users", "Users in each room over every match, bots included.", "gauge");
+
+        let room_users: Vec<i64> = match self.room_users.lock() {
+            Ok(room_users) => room_users.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        };
+
+        for (room_idx, users) in room_users.iter().enumerate() {
+            out.push_str(&format!("server_room_users{{room_idx=\"{}\"}} {}\n", room_idx, users));
+        }
+
+        self.tick_seconds.write(&mut out, "server_tick_duration_seconds", "Time spent running a match tick.");
+        counter(&mut out, "server_tick_overruns_total", "Ticks that took longer than the time between ticks.", &self.tick_overruns);
+        counter(&mut out, "server_ticks_skipped_total", "Ticks given up on after falling behind schedule.", &self.ticks_skipped);
+        self.tick_sent_bytes.write(&mut out, "server_tick_sent_bytes", "Bytes of geometry and snapshots a match queued for its clients in a tick.");
+        counter(&mut out, "server_dropped_frames_total", "Snapshots and messages dropped because a client couldn't keep up.", &self.dropped_frames);
+        counter(&mut out, "server_shots_fired_total", "Shots fired, hitscan and projectile.", &self.shots_fired);
+        counter(&mut out, "server_hits_total", "Shots that hit a user.", &self.hits);
+        counter(&mut out, "server_kills_total", "Users killed, penalties included.", &self.kills);
+        counter(&mut out, "server_client_decode_errors_total", "Client messages that failed to decode.", &self.decode_errors);
+
+        out
+
+    }
+
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    out.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n", name, help, name, kind));
+}
+
+fn counter(out: &mut String, name: &str, help: &str, counter: &Counter) {
+    header(out, name, help, "counter");
+    out.push_str(&format!("{} {}\n", name, counter.get()));
+}
+
+// answers GET /metrics on every connection, one request each
+pub async fn serve(listener: tokio::net::TcpListener, span: log::Span) {
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                tokio::spawn(respond(stream, span.child("scrape").with("peer", peer)));
+            }
+            Err(err) => return span.error("failed to accept a metrics connection", &[("err", &err)]),
+        }
+    }
+
+}
+
+async fn respond(mut stream: tokio::net::TcpStream, span: log::Span) {
+
+    let request: Option<Vec<u8>> = match tokio::time::timeout(tokio::time::Duration::from_millis(REQUEST_TIMEOUT_MS), read_request(&mut stream)).await {
+        Ok(Ok(request)) => request,
+        Ok(Err(err)) => return span.debug("failed to read metrics request", &[("err", &err)]),
+        Err(_) => return span.debug("metrics request timed out", &[]),
+    };
+
+    // only the request line is looked at, headers and bodies are ignored
+    let request_line: Option<Vec<&[u8]>> = request
+        .as_ref()
+        .and_then(|request| request.split(|byte| *byte == b'\n').next())
+        .map(|request_line| request_line.trim_ascii().split(|byte| *byte == b' ').collect());
+
+    let (status, body): (&str, String) = match request_line.as_deref() {
+        Some([b"GET", b"/metrics", _]) => ("200 OK", METRICS.render()),
+        Some([b"GET", _, _]) => ("404 Not Found", String::from("not found\n")),
+        Some([_, _, _]) => ("405 Method Not Allowed", String::from("method not allowed\n")),
+        _ => ("400 Bad Request", String::from("bad request\n")),
+    };
+
+    let response: String = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, body.len(), body,
+    );
+
+    if let Err(err) = stream.write_all(response.as_bytes()).await {
+        return span.debug("failed to send metrics response", &[("err", &err)]);
+    }
+
+    let _ = stream.shutdown().await;
+
+}
+
+// the request up to the end of its headers, None if it doesn't fit in MAX_REQUEST_BYTES
+async fn read_request(stream: &mut tokio::net::TcpStream) -> std::io::Result<Option<Vec<u8>>> {
+
+    let mut request: Vec<u8> = Vec::new();
+    let mut buf: [u8; 1024] = [0; 1024];
+
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+
+        if request.len() >= MAX_REQUEST_BYTES {
+            return Ok(None);
+        }
+
+        let read: usize = stream.read(&mut buf).await?;
+
+        if read == 0 {
+            break;
+        }
+
+        request.extend_from_slice(&buf[..read]);
+
+    }
+
+    Ok(Some(request))
+
+}
@@ -0,0 +1,553 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use crate::{ entity, room, user };
+
+// every surface a user can stand on in every room, linked by the moves that get a user from one to another,
+// built once per set of rooms
+pub struct NavGraph {
+    pub surfaces: Vec<Surface>,
+    // indexed like surfaces
+    pub edges: Vec<Vec<Edge>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Surface {
+    pub room_idx: usize,
+    pub kind: SurfaceKind,
+    pub x_min: f32,
+    pub x_max: f32,
+    // where a standing user's feet are
+    pub y: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SurfaceKind {
+    Floor,
+    // platform idx, platforms can be dropped through
+    Platform(usize),
+    // solid idx
+    Solid(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Edge {
+    pub to: usize,
+    pub kind: EdgeKind,
+    // where to be on the surface (left edge of the user) to take the edge, and where it lands on the next one
+    pub from_x: f32,
+    pub to_x: f32,
+    pub cost: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EdgeKind {
+    Jump,
+    // run off the end of the surface
+    Fall,
+    // hold down to drop through a platform
+    Drop,
+    // door idx in the surface's room
+    Door(usize),
+}
+
+// costs are in pixels, moves that leave the ground are a little more expensive than walking the same distance
+const AIR_COST: f32 = 10.0;
+// anything longer is a fall out of the world, not a jump
+const MAX_AIR_TICKS: u32 = 600;
+
+impl NavGraph {
+
+    pub fn build(rooms: &[room::Room], physics: &user::Physics) -> Self {
+
+        let mut surfaces: Vec<Surface> = Vec::new();
+
+        for (room_idx, room) in rooms.iter().enumerate() {
+
+            let mut room_surfaces: Vec<Surface> = vec![Surface { room_idx, kind: SurfaceKind::Floor, x_min: 0.0, x_max: room.bounds.x_max, y: room.bounds.y_max }];
+
+            for (platform_idx, platform) in room.platforms.iter().enumerate() {
+                room_surfaces.push(Surface {
+                    room_idx,
+                    kind: SurfaceKind::Platform(platform_idx),
+                    x_min: platform.x,
+                    x_max: platform.x + platform.width,
+                    y: platform.y,
+                });
+            }
+
+            for (solid_idx, solid) in room.solids.iter().enumerate() {
+                room_surfaces.push(Surface {
+                    room_idx,
+                    kind: SurfaceKind::Solid(solid_idx),
+                    x_min: solid.x,
+                    x_max: solid.x + solid.width,
+                    y: solid.y,
+                });
+            }
+
+            for surface in room_surfaces {
+                surfaces.extend(split_by_solids(surface, &room.solids));
+            }
+
+        }
+
+        let mut edges: Vec<Vec<Edge>> = vec![Vec::new(); surfaces.len()];
+
+        for (from, from_surface) in surfaces.iter().enumerate() {
+
+            let room: &room::Room = &rooms[from_surface.room_idx];
+
+            for (to, to_surface) in surfaces.iter().enumerate() {
+
+                if to == from || to_surface.room_idx != from_surface.room_idx {
+                    continue;
+                }
+
+                if let Some(edge) = air_edge(from_surface, to_surface, to, room, physics) {
+                    edges[from].push(edge);
+                }
+
+            }
+
+        }
+
+        for (room_idx, room) in rooms.iter().enumerate() {
+            for (door_idx, door) in room.doors.iter().enumerate() {
+
+                let linked_door: &room::Door = &rooms[door.room_idx].doors[door.door_idx];
+
+                let (from, to) = match (standing_surface(&surfaces, room_idx, &door.entity), standing_surface(&surfaces, door.room_idx, &linked_door.entity)) {
+                    (Some(from), Some(to)) => (from, to),
+                    _ => continue,
+                };
+
+                // doors are walked into from the inside of the room, and walked out of on the inside of the linked room
+                let from_x: f32 = (door.entity.x + door.entity.width * 0.5 - user::User::SIZE * 0.5).clamp(surfaces[from].x_min, surfaces[from].x_max - user::User::SIZE);
+                let to_x: f32 = match linked_door.entity.x + linked_door.entity.width * 0.5 < rooms[door.room_idx].bounds.x_max * 0.5 {
+                    true => linked_door.entity.x + linked_door.entity.width,
+                    false => linked_door.entity.x - user::User::SIZE,
+                };
+
+                edges[from].push(Edge { to, kind: EdgeKind::Door(door_idx), from_x, to_x, cost: AIR_COST });
+
+            }
+        }
+
+        Self { surfaces, edges }
+
+    }
+
+    // the surface under an entity in a room, the floor if nothing else is
+    pub fn surface_under(&self, room_idx: usize, entity: &entity::Entity) -> Option<usize> {
+
+        let feet: f32 = entity.y + entity.height;
+
+        self.surfaces
+            .iter()
+            .enumerate()
+            .filter(|(_, surface)| surface.room_idx == room_idx && overlaps(surface, entity.x, entity.x + entity.width) && surface.y >= feet - 1.0)
+            .min_by(|(_, a), (_, b)| a.y.total_cmp(&b.y))
+            .map(|(surface_idx, _)| surface_idx)
+
+    }
+
+    // A* over surfaces, the returned edges lead from one surface to the other in order (empty if they're the same)
+    pub fn find_path(&self, from: usize, to: usize) -> Option<Vec<Edge>> {
+
+        let goal: &Surface = &self.surfaces[to];
+
+        // surfaces in the goal's room with a door out, the goal might be closer through another room
+        let exits: Vec<f32> = self.surfaces
+            .iter()
+            .zip(&self.edges)
+            .filter(|(surface, edges)| surface.room_idx == goal.room_idx && edges.iter().any(|edge| matches!(edge.kind, EdgeKind::Door(_))))
+            .map(|(surface, _)| surface.y)
+            .collect();
+
+        // edges within a room cost at least the height they cover and doors cost AIR_COST, so a path within the goal's
+        // room costs at least the height difference, and one through a door at least the height to that door and the door
+        let heuristic = |surface_idx: usize| -> f32 {
+            let surface: &Surface = &self.surfaces[surface_idx];
+            match surface.room_idx == goal.room_idx {
+                true => exits.iter().map(|y| (surface.y - y).abs() + AIR_COST).fold((surface.y - goal.y).abs(), f32::min),
+                false => 0.0,
+            }
+        };
+
+        let mut costs: Vec<f32> = vec![f32::INFINITY; self.surfaces.len()];
+        let mut came_from: Vec<Option<(usize, Edge)>> = vec![None; self.surfaces.len()];
+        let mut open: BinaryHeap<Open> = BinaryHeap::new();
+
+        costs[from] = 0.0;
+        open.push(Open { estimate: heuristic(from), surface_idx: from });
+
+        while let Some(Open { surface_idx, .. }) = open.pop() {
+
+            if surface_idx == to {
+
+                let mut path: Vec<Edge> = Vec::new();
+                let mut current: usize = to;
+
+                while let Some((previous, edge)) = came_from[current] {
+                    path.push(edge);
+                    current = previous;
+                }
+
+                path.reverse();
+
+                return Some(path);
+
+            }
+
+            for edge in &self.edges[surface_idx] {
+
+                let cost: f32 = costs[surface_idx] + edge.cost;
+
+                if cost < costs[edge.to] {
+                    costs[edge.to] = cost;
+                    came_from[edge.to] = Some((surface_idx, *edge));
+                    open.push(Open { estimate: cost + heuristic(edge.to), surface_idx: edge.to });
+                }
+
+            }
+
+        }
+
+        None
+
+    }
+
+}
+
+// min heap entry for A*
+struct Open {
+    estimate: f32,
+    surface_idx: usize,
+}
+
+impl PartialEq for Open {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Open {}
+
+impl PartialOrd for Open {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Open {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.estimate.total_cmp(&self.estimate)
+    }
+}
+
+fn overlaps(surface: &Surface, x_min: f32, x_max: f32) -> bool {
+    surface.x_min < x_max && x_min < surface.x_max
+}
+
+// the parts of a surface a user fits on without standing inside a solid, jumping between them gets over the solid
+fn split_by_solids(surface: Surface, solids: &[entity::Entity]) -> Vec<Surface> {
+
+    let mut parts: Vec<Surface> = vec![surface];
+
+    for solid in solids {
+
+        // only solids in the space a user standing on the surface takes up
+        if solid.y >= surface.y || solid.y + solid.height <= surface.y - user::User::SIZE {
+            continue;
+        }
+
+        parts = parts
+            .into_iter()
+            .flat_map(|part| [
+                Surface { x_max: part.x_max.min(solid.x), ..part },
+                Surface { x_min: part.x_min.max(solid.x + solid.width), ..part },
+            ])
+            .filter(|part| part.x_max - part.x_min >= user::User::SIZE)
+            .collect();
+
+    }
+
+    parts
+
+}
+
+fn standing_surface(surfaces: &[Surface], room_idx: usize, entity: &entity::Entity) -> Option<usize> {
+    surfaces
+        .iter()
+        .position(|surface| surface.room_idx == room_idx && overlaps(surface, entity.x, entity.x + entity.width) && (surface.y - (entity.y + entity.height)).abs() < 1.0)
+}
+
+// ticks in the air until a user launched upward at speed (positive is up) comes back down to height (relative to
+// where it started), following the same order as User::tick: move, then fall
+fn air_ticks(speed: f32, gravity: f32, height: f32) -> Option<u32> {
+
+    if gravity <= 0.0 {
+        return None;
+    }
+
+    let mut y: f32 = 0.0;
+    let mut dy: f32 = speed;
+
+    for tick in 1..=MAX_AIR_TICKS {
+
+        y += dy;
+        dy -= gravity;
+
+        if dy < 0.0 && y <= height {
+            return Some(tick);
+        }
+
+    }
+
+    None
+
+}
+
+// whether a user leaving the surface at from_x (upward at speed) lands at to_x when they come back down to height,
+// running at full speed toward it, they're held back by solids, doors and the sides of the room like in User::tick
+// and anything hit from above or below (the ceiling too) spoils the move
+fn clear_flight(room: &room::Room, from_surface: &Surface, height: f32, from_x: f32, to_x: f32, speed: f32, physics: &user::Physics) -> bool {
+
+    let ticks: u32 = match air_ticks(speed, room.gravity, height) {
+        Some(ticks) => ticks,
+        None => return false,
+    };
+
+    let user_at = |x: f32, y: f32| entity::Entity { x, y: from_surface.y - y - user::User::SIZE, width: user::User::SIZE, height: user::User::SIZE };
+    let blocked = |entity: &entity::Entity| room.traps(entity) || room.doors.iter().any(|door| entity.overlaps(&door.entity));
+
+    let mut x: f32 = from_x;
+    let mut y: f32 = 0.0;
+    let mut dy: f32 = speed;
+
+    for tick in 1..=ticks {
+
+        let next_x: f32 = match (to_x - x).abs() <= physics.run_max_speed {
+            true => to_x,
+            false => x + physics.run_max_speed.copysign(to_x - x),
+        };
+
+        if !blocked(&user_at(next_x, y)) {
+            x = next_x;
+        }
+
+        // the last tick lands on the surface rather than in it
+        y = match tick == ticks {
+            true => height,
+            false => y + dy,
+        };
+
+        dy -= room.gravity;
+
+        if blocked(&user_at(x, y)) {
+            return false;
+        }
+
+    }
+
+    x == to_x
+
+}
+
+// a jump or fall from one surface to another in the same room, if a user running at full speed can make it
+fn air_edge(from_surface: &Surface, to_surface: &Surface, to: usize, room: &room::Room, physics: &user::Physics) -> Option<Edge> {
+
+    // positive when the next surface is higher
+    let height: f32 = from_surface.y - to_surface.y;
+
+    let width: f32 = user::User::SIZE;
+
+    // dropping through a platform lands straight below it
+    if height < 0.0 && overlaps(to_surface, from_surface.x_min, from_surface.x_max) {
+
+        if let SurfaceKind::Platform(_) = from_surface.kind {
+
+            let x: f32 = from_surface.x_min.max(to_surface.x_min) + (from_surface.x_max.min(to_surface.x_max) - from_surface.x_min.max(to_surface.x_min) - width) * 0.5;
+
+            return clear_flight(room, from_surface, height, x, x, 0.0, physics).then_some(Edge { to, kind: EdgeKind::Drop, from_x: x, to_x: x, cost: -height + AIR_COST });
+
+        }
+
+    }
+
+    let jump_speed: f32 = -physics.jump_force / physics.weight;
+
+    // running off an edge is only worth it when the surface below sticks out past it
+    if height < 0.0 {
+
+        let fall: Option<(f32, f32)> = if to_surface.x_max > from_surface.x_max {
+            Some((from_surface.x_max, to_surface.x_min.max(from_surface.x_max)))
+        } else if to_surface.x_min < from_surface.x_min {
+            Some((from_surface.x_min - width, to_surface.x_max.min(from_surface.x_min) - width))
+        } else {
+            None
+        };
+
+        if let Some((from_x, to_x)) = fall {
+            if clear_flight(room, from_surface, height, from_x, to_x, 0.0, physics) {
+                return Some(Edge { to, kind: EdgeKind::Fall, from_x, to_x, cost: (to_x - from_x).abs() - height + AIR_COST });
+            }
+        }
+
+    }
+
+    let (from_x, to_x) = if overlaps(to_surface, from_surface.x_min, from_surface.x_max) {
+        let x: f32 = from_surface.x_min.max(to_surface.x_min) + (from_surface.x_max.min(to_surface.x_max) - from_surface.x_min.max(to_surface.x_min) - width) * 0.5;
+        (x, x)
+    } else if to_surface.x_min >= from_surface.x_max {
+        (from_surface.x_max - width, to_surface.x_min)
+    } else {
+        (from_surface.x_min, to_surface.x_max - width)
+    };
+
+    if !clear_flight(room, from_surface, height, from_x, to_x, jump_speed, physics) {
+        return None;
+    }
+
+    Some(Edge { to, kind: EdgeKind::Jump, from_x, to_x, cost: (to_x - from_x).abs() + height.abs() + AIR_COST })
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn entity(x: f32, y: f32, width: f32, height: f32) -> entity::Entity {
+        entity::Entity { x, y, width, height }
+    }
+
+    fn room(x_max: f32, y_max: f32, platforms: Vec<entity::Entity>, solids: Vec<entity::Entity>, doors: Vec<room::Door>) -> room::Room {
+        room::Room {
+            gravity: 1.5,
+            bounds: room::Bounds { x_max, y_max },
+            platforms,
+            walls: Vec::new(),
+            solids,
+            moving_platforms: Vec::new(),
+            doors,
+            spawns: Vec::new(),
+        }
+    }
+
+    fn surface_idx(graph: &NavGraph, room_idx: usize, x: f32, y: f32) -> usize {
+        graph.surfaces
+            .iter()
+            .position(|surface| surface.room_idx == room_idx && surface.x_min <= x && x < surface.x_max && surface.y == y)
+            .expect("surface exists")
+    }
+
+    fn edge_kinds(graph: &NavGraph, from: usize, to: usize) -> Vec<EdgeKind> {
+        graph.edges[from].iter().filter(|edge| edge.to == to).map(|edge| edge.kind).collect()
+    }
+
+    #[test]
+    fn platform_within_a_jump_is_linked_both_ways() {
+
+        let graph: NavGraph = NavGraph::build(&[room(200.0, 200.0, vec![entity(80.0, 160.0, 40.0, 3.0)], Vec::new(), Vec::new())], &user::Physics::default());
+
+        let floor: usize = surface_idx(&graph, 0, 0.0, 200.0);
+        let platform: usize = surface_idx(&graph, 0, 80.0, 160.0);
+
+        assert_eq!(graph.surfaces.len(), 2);
+        assert_eq!(edge_kinds(&graph, floor, platform), vec![EdgeKind::Jump]);
+        assert_eq!(edge_kinds(&graph, platform, floor), vec![EdgeKind::Drop]);
+
+        let path: Vec<Edge> = graph.find_path(floor, platform).unwrap();
+
+        assert_eq!(path.len(), 1);
+        assert_eq!(path[0].kind, EdgeKind::Jump);
+        assert_eq!(graph.find_path(floor, floor), Some(Vec::new()));
+
+    }
+
+    #[test]
+    fn ceiling_blocks_a_jump() {
+
+        // the platform is low enough to reach, the ceiling right above it isn't high enough to get there
+        let graph: NavGraph = NavGraph::build(&[room(200.0, 60.0, vec![entity(80.0, 20.0, 40.0, 3.0)], Vec::new(), Vec::new())], &user::Physics::default());
+
+        let floor: usize = surface_idx(&graph, 0, 0.0, 60.0);
+        let platform: usize = surface_idx(&graph, 0, 80.0, 20.0);
+
+        assert!(edge_kinds(&graph, floor, platform).is_empty());
+        assert_eq!(graph.find_path(floor, platform), None);
+
+    }
+
+    #[test]
+    fn solid_in_the_way_blocks_a_jump() {
+
+        // a solid too tall to jump over splits the floor, the platform right behind it is only reached from the other side
+        let rooms: Vec<room::Room> = vec![room(300.0, 200.0, vec![entity(135.0, 160.0, 60.0, 3.0)], vec![entity(120.0, 100.0, 10.0, 100.0)], Vec::new())];
+        let graph: NavGraph = NavGraph::build(&rooms, &user::Physics::default());
+
+        let floor_left: usize = surface_idx(&graph, 0, 0.0, 200.0);
+        let floor_right: usize = surface_idx(&graph, 0, 130.0, 200.0);
+        let platform: usize = surface_idx(&graph, 0, 135.0, 160.0);
+        let solid: usize = surface_idx(&graph, 0, 120.0, 100.0);
+
+        assert_eq!(graph.surfaces[floor_left].x_max, 120.0);
+        assert_eq!(graph.surfaces[floor_right].x_min, 130.0);
+        assert_eq!(graph.surfaces[solid].kind, SurfaceKind::Solid(0));
+
+        assert!(edge_kinds(&graph, floor_left, platform).is_empty());
+        assert!(edge_kinds(&graph, floor_left, floor_right).is_empty());
+        assert_eq!(edge_kinds(&graph, floor_right, platform), vec![EdgeKind::Jump]);
+
+        assert_eq!(graph.find_path(floor_left, platform), None);
+
+    }
+
+    #[test]
+    fn doors_link_rooms() {
+
+        let rooms: Vec<room::Room> = vec![
+            room(200.0, 200.0, Vec::new(), Vec::new(), vec![room::Door { entity: entity(190.0, 150.0, 10.0, 50.0), room_idx: 1, door_idx: 0 }]),
+            room(200.0, 200.0, Vec::new(), Vec::new(), vec![room::Door { entity: entity(0.0, 150.0, 10.0, 50.0), room_idx: 0, door_idx: 0 }]),
+        ];
+        let graph: NavGraph = NavGraph::build(&rooms, &user::Physics::default());
+
+        let from: usize = surface_idx(&graph, 0, 0.0, 200.0);
+        let to: usize = surface_idx(&graph, 1, 0.0, 200.0);
+
+        let path: Vec<Edge> = graph.find_path(from, to).unwrap();
+
+        assert_eq!(path.len(), 1);
+        assert_eq!(path[0].kind, EdgeKind::Door(0));
+        // walked into on the inside of the room, walked out of on the inside of the other one
+        assert_eq!(path[0].from_x, 190.0);
+        assert_eq!(path[0].to_x, 10.0);
+
+    }
+
+    #[test]
+    fn cheaper_path_through_other_rooms_is_found() {
+
+        let surface = |room_idx: usize, y: f32| Surface { room_idx, kind: SurfaceKind::Floor, x_min: 0.0, x_max: 100.0, y };
+        let edge = |to: usize, kind: EdgeKind, cost: f32| Edge { to, kind, from_x: 0.0, to_x: 0.0, cost };
+
+        // a long climb straight to the goal, or through doors via another surface in the goal's room as low as the start
+        let graph: NavGraph = NavGraph {
+            surfaces: vec![surface(0, 300.0), surface(0, 220.0), surface(1, 300.0), surface(0, 300.0), surface(1, 300.0)],
+            edges: vec![
+                vec![edge(1, EdgeKind::Jump, 90.0), edge(2, EdgeKind::Door(0), AIR_COST)],
+                Vec::new(),
+                vec![edge(3, EdgeKind::Door(0), AIR_COST)],
+                vec![edge(4, EdgeKind::Door(1), AIR_COST)],
+                vec![edge(1, EdgeKind::Door(1), AIR_COST)],
+            ],
+        };
+
+        let path: Vec<Edge> = graph.find_path(0, 1).unwrap();
+
+        assert_eq!(path.iter().map(|edge| edge.to).collect::<Vec<usize>>(), vec![2, 3, 4, 1]);
+
+    }
+
+}
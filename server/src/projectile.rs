@@ -0,0 +1,119 @@
+use crate::{ entity, protocol, ray, room, user };
+
+// a shot from a projectile weapon, it flies until it hits something, runs out of range or leaves the room other than through a door
+pub struct Projectile {
+    pub user_idx: usize,
+    pub room_idx: usize,
+    pub x: f32,
+    pub y: f32,
+    pub dx: f32,
+    pub dy: f32,
+    pub gravity_scale: f32,
+    // how much further it can fly
+    pub range: f32,
+}
+
+impl Projectile {
+
+    pub fn from_ray(ray: &ray::Ray, user_idx: usize, room_idx: usize, speed: f32, gravity_scale: f32, range: f32) -> Self {
+        Self {
+            user_idx,
+            room_idx,
+            x: ray.origin_x,
+            y: ray.origin_y,
+            dx: ray.direction_x * speed,
+            dy: ray.direction_y * speed,
+            gravity_scale,
+            range,
+        }
+    }
+
+    // moves the projectile one tick against where users are now, returns whether it's still flying and the user it hit,
+    // a projectile flying into a door comes out of the linked one in its room
+    pub fn tick(&mut self, users: &[Option<user::User>], rooms: &[room::Room], rooms_mut: &[room::RoomMut]) -> (bool, Option<usize>) {
+
+        let room: &room::Room = &rooms[self.room_idx];
+
+        let speed: f32 = f32::sqrt(self.dx * self.dx + self.dy * self.dy);
+        let distance: f32 = speed.min(self.range);
+
+        if distance > 0.0 {
+
+            let ray: ray::Ray = ray::Ray {
+                origin_x: self.x,
+                origin_y: self.y,
+                direction_x: self.dx / speed,
+                direction_y: self.dy / speed,
+            };
+
+            let hit_user_idx: Option<(usize, f32)> = users
+                .iter()
+                .flatten()
+                .filter(|user| user.idx as usize != self.user_idx && user.room_idx == self.room_idx)
+                .filter_map(|user| ray.entry(&user.dynamic_entity.entity).map(|entry| (user.idx as usize, entry)))
+                .filter(|(_, entry)| *entry <= distance)
+                .min_by(|(_, a), (_, b)| a.total_cmp(b));
+
+            let obstacle: Option<(Option<usize>, f32)> = room.platforms
+                .iter()
+                .chain(room.walls.iter())
+                .chain(room.solids.iter())
+                .chain(rooms_mut[self.room_idx].moving_platforms.iter().map(|state| &state.entity))
+                .map(|entity| (None, entity))
+                .chain(room.doors.iter().enumerate().map(|(door_idx, door)| (Some(door_idx), &door.entity)))
+                .filter_map(|(door_idx, entity)| ray.entry(entity).map(|entry| (door_idx, entry)))
+                .filter(|(_, entry)| *entry <= distance)
+                .min_by(|(_, a), (_, b)| a.total_cmp(b));
+
+            match (hit_user_idx, obstacle) {
+                (Some((idx, user_distance)), Some((_, obstacle_distance))) if user_distance < obstacle_distance => return (false, Some(idx)),
+                (Some((idx, _)), None) => return (false, Some(idx)),
+                // doors are walked through sideways, so only projectiles moving sideways go through them
+                (_, Some((Some(door_idx), door_distance))) if self.dx != 0.0 => {
+                    self.pass_through(&room.doors[door_idx], door_distance, rooms);
+                    return (true, None);
+                }
+                (_, Some(_)) => return (false, None),
+                (None, None) => (),
+            }
+
+            self.x += ray.direction_x * distance;
+            self.y += ray.direction_y * distance;
+            self.range -= distance;
+
+        }
+
+        self.dy += room.gravity * self.gravity_scale;
+
+        let in_bounds: bool = self.x >= 0.0 && self.x <= room.bounds.x_max && self.y >= 0.0 && self.y <= room.bounds.y_max;
+
+        (self.range > 0.0 && in_bounds, None)
+
+    }
+
+    // out the far side of the linked door, as far down it as it went into this one
+    fn pass_through(&mut self, door: &room::Door, distance: f32, rooms: &[room::Room]) {
+
+        let linked_door: &entity::Entity = &rooms[door.room_idx].doors[door.door_idx].entity;
+
+        let speed: f32 = f32::sqrt(self.dx * self.dx + self.dy * self.dy);
+        let y: f32 = self.y + distance * self.dy / speed;
+
+        self.x = match self.dx > 0.0 {
+            true => linked_door.x + linked_door.width,
+            false => linked_door.x,
+        };
+        self.y = linked_door.y + (y - door.entity.y);
+        self.range -= distance;
+        self.room_idx = door.room_idx;
+
+    }
+
+    pub fn to_point(&self) -> protocol::Point {
+        protocol::Point {
+            x: self.x as u16,
+            y: self.y as u16,
+        }
+    }
+
+}
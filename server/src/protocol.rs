@@ -0,0 +1,1021 @@
+//! Binary wire protocol shared by the server, the browser client and bots.
+//!
+//! Every websocket message is a single binary frame starting with a one byte tag.
+//! Integers are big endian, counts are `u16`, strings are a `u8` length followed by utf-8.
+//!
+//! A connection always starts with `ClientMessage::Hello`, the server answers with
+//! `ServerMessage::Welcome` or `ServerMessage::Rejected` (and closes the socket).
+//!
+//! client -> server
+//!
+//! | tag | message       | body                          |
+//! |-----|---------------|-------------------------------|
+//! | 0   | Hello         | version: u16                  |
+//! | 1   | ListMatches   |                               |
+//! | 2   | CreateMatch   |                               |
+//! | 3   | JoinMatch     | match_id: u32                 |
+//! | 4   | Input         | seq: u32, input: u8 (see `Input`) |
+//! | 5   | Click         | seq: u32, view_tick: u32, x: u16, y: u16 |
+//! | 6   | Ack           | tick: u32                     |
+//! | 7   | SpectateMatch | match_id: u32, room_idx: u16  |
+//! | 8   | WatchRoom     | room_idx: u16                 |
+//! | 9   | AddBot        | difficulty: u8 (0 easy, 1 normal, 2 hard) |
+//! | 10  | RemoveBot     |                               |
+//! | 11  | SwitchWeapon  | seq: u32, weapon_idx: u8      |
+//!
+//! server -> client
+//!
+//! | tag | message       | body                                                    |
+//! |-----|---------------|---------------------------------------------------------|
+//! | 0   | Welcome       | version: u16                                            |
+//! | 1   | Rejected      | reason: string                                          |
+//! | 2   | MatchList     | count, match_id: u32 * count                            |
+//! | 3   | Joined        | match_id: u32, user_idx: u8                             |
+//! | 4   | JoinFailed    |                                                         |
+//! | 5   | Snapshot      | tick: u32, baseline, users, removed_users, bullet_paths, projectiles, moving_platforms, footer |
+//! | 6   | RoomGeometry  | room_idx: u16, platforms, doors, walls, solids          |
+//! | 7   | Spectating    | match_id: u32                                           |
+//! | 8   | SpectatorSnapshot | tick: u32, baseline, users, removed_users, bullet_paths, projectiles, moving_platforms |
+//! | 9   | MatchPhase    | phase: u8 (0 warmup, 1 live, 2 ended), end_tick         |
+//! | 10  | Scoreboard    | winner, scores                                          |
+//! | 11  | Killed        | respawn_tick: u32                                       |
+//! | 12  | TickStats     | tick: u32, tick_rate: u16, ticks: u16, skipped: u16, mean_tick_us: u32, max_tick_us: u32, max_late_us: u32 |
+//! | 13  | Kicked        | reason: string                                          |
+//!
+//! `SpectateMatch` takes the place of `JoinMatch` for clients that only want to watch. Spectators have no user, they are
+//! sent `SpectatorSnapshot`s (a snapshot without a footer) of the room they are watching, can switch rooms with `WatchRoom`
+//! and may only send `Ack` and `WatchRoom` while in the match.
+//!
+//! Users in a match can add server controlled bots with `AddBot`, `RemoveBot` removes the most recently added one.
+//!
+//! `MatchPhase` is sent on joining and whenever the phase changes. Its `end_tick` is `0`, or `1` followed by a `tick: u32`
+//! when the warmup countdown or the time limit runs out. Nothing is scored during warmup. Once the match has ended every
+//! user and spectator is sent a `Scoreboard` and the match closes. Its `winner` is `0`, or `1` followed by a `user_idx: u8`.
+//! Users eliminated in a last hunter standing match are sent `Spectating` and watch the room they died in until the end.
+//!
+//! A user who is killed is sent `Killed` and has no user until `respawn_tick`, meanwhile they are sent `SpectatorSnapshot`s
+//! of the room they died in. Users (re)spawn at one of the rooms' spawn points away from their hunter and whoever killed
+//! them, and are `protected` from being killed until they shoot or a few seconds pass.
+//!
+//! `RoomGeometry` is sent whenever a user enters a room (including joining and respawning) and again when the server
+//! reloads its rooms, a user the new geometry traps is moved to a spawn point.
+//! Platforms are only stood on from above, walls only stop shots (users walk in front of them), solids block users
+//! from every side and stop shots. Moving platforms aren't part of the geometry, every snapshot lists where each of the
+//! room's moving platforms is (as a `Rect`) in the same order every tick.
+//!
+//! A snapshot's baseline is `0` for a keyframe, or `1` followed by a `tick: u32` the client previously acked.
+//! The client rebuilds the room's users by taking the users of the baseline snapshot, replacing `users` and dropping
+//! `removed_users`. Keyframes list every user in the room. Bullet paths only exist for the tick they are fired in, they
+//! end at the room's bounds and a shot through a door continues as another path in the linked room.
+//! Clients should `Ack` every snapshot they apply, snapshots older than `snapshot::History::LEN` ticks can't be used as a baseline.
+//!
+//! `Input` and `Click` carry a client chosen sequence number that should increase by one per message. The footer reports
+//! the last sequence number the server applied before the snapshot's tick along with the receiving user's authoritative
+//! movement state, so a client predicting its own movement can reset to that state and replay any newer inputs.
+//!
+//! Shooting anyone but your target is penalized by the match's rules, the shooter may be stunned (no running, jumping
+//! or shooting while `stun_ticks` is above zero) or revealed. A footer's `target_reveal` is `0`, or `1` followed by a
+//! `Reveal` of the receiving user's target while the target is revealed, even when they're in another room.
+//!
+//! `Click::view_tick` is the tick of the snapshot the client was showing when the user clicked. Shots are resolved
+//! against where users were at that tick, up to the server's `max_rewind_ticks` in the past.
+//!
+//! Users carry the match's weapons (`rules::Rules::weapons`) and shoot the one selected with `SwitchWeapon`, clicks
+//! are ignored while it's cooling down or reloading. Weapons with a magazine reload once it's empty or on
+//! `Input::Reload`. Hitscan weapons show up as bullet paths, projectile weapons fire projectiles that fly (and fall)
+//! for a number of ticks, a snapshot lists where each projectile in the room is. The footer's `loadout` is the
+//! receiving user's selected weapon and how much it has left.
+//!
+//! Matches tick `tick_rate` times a second on a fixed schedule, every user and spectator is sent `TickStats` once per
+//! `tick_rate` scheduled ticks. `tick` is the number of the last tick run since the match started (it keeps counting
+//! while the simulation waits for users, unlike snapshot ticks), `ticks` how many were run in the window and `skipped`
+//! how many were given up on because the match fell too far behind. `mean_tick_us` and `max_tick_us` are how long
+//! running a tick took, `max_late_us` how far past its deadline a tick started.
+//!
+//! A client that can't keep up is only sent the newest snapshot, older ones it hasn't been sent yet are dropped. One
+//! that keeps missing snapshots is sent one every 2nd, then 4th and 8th tick (going back up once it keeps up again),
+//! and is sent `Kicked` and disconnected if it still can't keep up.
+//!
+//! snapshot items
+//!
+//! | item       | body                                                |
+//! |------------|-----------------------------------------------------|
+//! | Rect       | width: u8, height: u8, x: u16, y: u16               |
+//! | Line       | origin_x: u16, origin_y: u16, end_x: u16, end_y: u16 |
+//! | UserState  | user_idx: u8, rect: Rect, protected: u8 (0 or 1)    |
+//! | Point      | x: u16, y: u16                                      |
+//! | Footer     | user_idx: u8, target_user_idx: u8, last_input_seq: u32, movement: Movement, loadout: Loadout, target_reveal |
+//! | Movement   | x: f32, y: f32, dx: f32, dy: f32, coyote_ticks: u8, jump_buffer_ticks: u8, stun_ticks: u16 |
+//! | Loadout    | weapon_idx: u8, ammo: u16, cooldown_ticks: u16, reload_ticks: u16 |
+//! | Reveal     | room_idx: u16, rect: Rect                           |
+//! | Score      | user_idx: u8, points: i16, kills: u16, deaths: u16, wrong_target_hits: u16, best_streak: u16 |
+
+pub const VERSION: u16 = 15;
+
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    Empty,
+    UnknownTag(u8),
+    UnknownInput(u8),
+    Truncated,
+    TrailingBytes(usize),
+    InvalidUtf8,
+}
+
+impl std::fmt::Display for DecodeError {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+
+        match self {
+            DecodeError::Empty => write!(f, "empty message"),
+            DecodeError::UnknownTag(tag) => write!(f, "unknown message tag {}", tag),
+            DecodeError::UnknownInput(input) => write!(f, "unknown input {}", input),
+            DecodeError::Truncated => write!(f, "message is truncated"),
+            DecodeError::TrailingBytes(len) => write!(f, "{} unexpected bytes after message", len),
+            DecodeError::InvalidUtf8 => write!(f, "string is not valid utf-8"),
+        }
+
+    }
+
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Input {
+    UpStart,
+    UpEnd,
+    DownStart,
+    DownEnd,
+    LeftStart,
+    LeftEnd,
+    RightStart,
+    RightEnd,
+    Reload,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ClientMessage {
+    Hello { version: u16 },
+    ListMatches,
+    CreateMatch,
+    JoinMatch { match_id: u32 },
+    Input { seq: u32, input: Input },
+    Click { seq: u32, view_tick: u32, x: u16, y: u16 },
+    Ack { tick: u32 },
+    SpectateMatch { match_id: u32, room_idx: u16 },
+    WatchRoom { room_idx: u16 },
+    AddBot { difficulty: u8 },
+    RemoveBot,
+    SwitchWeapon { seq: u32, weapon_idx: u8 },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ServerMessage {
+    Welcome { version: u16 },
+    Rejected { reason: String },
+    MatchList { match_ids: Vec<u32> },
+    Joined { match_id: u32, user_idx: u8 },
+    JoinFailed,
+    Snapshot { snapshot: Snapshot, footer: Footer },
+    RoomGeometry(RoomGeometry),
+    Spectating { match_id: u32 },
+    SpectatorSnapshot { snapshot: Snapshot },
+    MatchPhase { phase: u8, end_tick: Option<u32> },
+    Scoreboard { winner_user_idx: Option<u8>, scores: Vec<Score> },
+    Killed { respawn_tick: u32 },
+    TickStats { tick: u32, tick_rate: u16, ticks: u16, skipped: u16, mean_tick_us: u32, max_tick_us: u32, max_late_us: u32 },
+    Kicked { reason: String },
+}
+
+// geometry any wider or taller can't be sent, see room::Room::validate
+pub const MAX_RECT_SIZE: u8 = u8::MAX;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Rect {
+    pub width: u8,
+    pub height: u8,
+    pub x: u16,
+    pub y: u16,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Line {
+    pub origin_x: u16,
+    pub origin_y: u16,
+    pub end_x: u16,
+    pub end_y: u16,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Point {
+    pub x: u16,
+    pub y: u16,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct UserState {
+    pub user_idx: u8,
+    pub rect: Rect,
+    pub protected: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RoomGeometry {
+    pub room_idx: u16,
+    pub platforms: Vec<Rect>,
+    pub doors: Vec<Rect>,
+    pub walls: Vec<Rect>,
+    pub solids: Vec<Rect>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Snapshot {
+    pub tick: u32,
+    pub baseline_tick: Option<u32>,
+    pub users: Vec<UserState>,
+    pub removed_users: Vec<u8>,
+    pub bullet_paths: Vec<Line>,
+    pub projectiles: Vec<Point>,
+    pub moving_platforms: Vec<Rect>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Movement {
+    pub x: f32,
+    pub y: f32,
+    pub dx: f32,
+    pub dy: f32,
+    pub coyote_ticks: u8,
+    pub jump_buffer_ticks: u8,
+    pub stun_ticks: u16,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Loadout {
+    pub weapon_idx: u8,
+    pub ammo: u16,
+    pub cooldown_ticks: u16,
+    pub reload_ticks: u16,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Reveal {
+    pub room_idx: u16,
+    pub rect: Rect,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Footer {
+    pub user_idx: u8,
+    pub target_user_idx: u8,
+    pub last_input_seq: u32,
+    pub movement: Movement,
+    pub loadout: Loadout,
+    pub target_reveal: Option<Reveal>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Score {
+    pub user_idx: u8,
+    pub points: i16,
+    pub kills: u16,
+    pub deaths: u16,
+    pub wrong_target_hits: u16,
+    pub best_streak: u16,
+}
+
+// also used to read replay files
+pub struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+
+        let bytes: &'a [u8] = self.buf.get(self.pos..self.pos + len).ok_or(DecodeError::Truncated)?;
+
+        self.pos += len;
+
+        Ok(bytes)
+
+    }
+
+    pub fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, DecodeError> {
+        Ok(u16::from_be_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, DecodeError> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, DecodeError> {
+        Ok(u64::from_be_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    pub fn i16(&mut self) -> Result<i16, DecodeError> {
+        Ok(i16::from_be_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    pub fn f32(&mut self) -> Result<f32, DecodeError> {
+        Ok(f32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn optional<T>(&mut self, decode: impl FnOnce(&mut Self) -> Result<T, DecodeError>) -> Result<Option<T>, DecodeError> {
+        match self.u8()? {
+            0 => Ok(None),
+            _ => Ok(Some(decode(self)?)),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, DecodeError> {
+        let len: usize = self.u8()? as usize;
+        String::from_utf8(self.bytes(len)?.to_vec()).map_err(|_| DecodeError::InvalidUtf8)
+    }
+
+    fn list<T>(&mut self, mut decode: impl FnMut(&mut Self) -> Result<T, DecodeError>) -> Result<Vec<T>, DecodeError> {
+
+        let len: usize = self.u16()? as usize;
+
+        (0..len).map(|_| decode(self)).collect()
+
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos == self.buf.len()
+    }
+
+    pub fn finish(&self) -> Result<(), DecodeError> {
+
+        match self.buf.len() - self.pos {
+            0 => Ok(()),
+            len => Err(DecodeError::TrailingBytes(len)),
+        }
+
+    }
+
+}
+
+// reasons are short and human readable, anything past 255 bytes is cut off at a char boundary
+fn encode_string(buf: &mut Vec<u8>, string: &str) {
+
+    let mut len: usize = string.len().min(u8::MAX as usize);
+
+    while !string.is_char_boundary(len) {
+        len -= 1;
+    }
+
+    buf.push(len as u8);
+    buf.extend_from_slice(&string.as_bytes()[..len]);
+
+}
+
+fn encode_optional<T>(buf: &mut Vec<u8>, item: &Option<T>, encode: impl Fn(&T, &mut Vec<u8>)) {
+    match item {
+        None => buf.push(0),
+        Some(item) => {
+            buf.push(1);
+            encode(item, buf);
+        }
+    }
+}
+
+fn encode_list<T>(buf: &mut Vec<u8>, items: &[T], encode: impl Fn(&T, &mut Vec<u8>)) {
+
+    buf.extend_from_slice(&(items.len() as u16).to_be_bytes());
+
+    for item in items {
+        encode(item, buf);
+    }
+
+}
+
+impl Input {
+
+    fn to_u8(self) -> u8 {
+        match self {
+            Input::UpStart => 0,
+            Input::UpEnd => 1,
+            Input::DownStart => 2,
+            Input::DownEnd => 3,
+            Input::LeftStart => 4,
+            Input::LeftEnd => 5,
+            Input::RightStart => 6,
+            Input::RightEnd => 7,
+            Input::Reload => 8,
+        }
+    }
+
+    fn from_u8(input: u8) -> Result<Self, DecodeError> {
+        match input {
+            0 => Ok(Input::UpStart),
+            1 => Ok(Input::UpEnd),
+            2 => Ok(Input::DownStart),
+            3 => Ok(Input::DownEnd),
+            4 => Ok(Input::LeftStart),
+            5 => Ok(Input::LeftEnd),
+            6 => Ok(Input::RightStart),
+            7 => Ok(Input::RightEnd),
+            8 => Ok(Input::Reload),
+            _ => Err(DecodeError::UnknownInput(input)),
+        }
+    }
+
+}
+
+impl ClientMessage {
+
+    pub fn encode(&self) -> Vec<u8> {
+
+        let mut buf: Vec<u8> = Vec::with_capacity(5);
+
+        match self {
+            ClientMessage::Hello { version } => {
+                buf.push(0);
+                buf.extend_from_slice(&version.to_be_bytes());
+            }
+            ClientMessage::ListMatches => buf.push(1),
+            ClientMessage::CreateMatch => buf.push(2),
+            ClientMessage::JoinMatch { match_id } => {
+                buf.push(3);
+                buf.extend_from_slice(&match_id.to_be_bytes());
+            }
+            ClientMessage::Input { seq, input } => {
+                buf.push(4);
+                buf.extend_from_slice(&seq.to_be_bytes());
+                buf.push(input.to_u8());
+            }
+            ClientMessage::Click { seq, view_tick, x, y } => {
+                buf.push(5);
+                buf.extend_from_slice(&seq.to_be_bytes());
+                buf.extend_from_slice(&view_tick.to_be_bytes());
+                buf.extend_from_slice(&x.to_be_bytes());
+                buf.extend_from_slice(&y.to_be_bytes());
+            }
+            ClientMessage::Ack { tick } => {
+                buf.push(6);
+                buf.extend_from_slice(&tick.to_be_bytes());
+            }
+            ClientMessage::SpectateMatch { match_id, room_idx } => {
+                buf.push(7);
+                buf.extend_from_slice(&match_id.to_be_bytes());
+                buf.extend_from_slice(&room_idx.to_be_bytes());
+            }
+            ClientMessage::WatchRoom { room_idx } => {
+                buf.push(8);
+                buf.extend_from_slice(&room_idx.to_be_bytes());
+            }
+            ClientMessage::AddBot { difficulty } => {
+                buf.push(9);
+                buf.push(*difficulty);
+            }
+            ClientMessage::RemoveBot => buf.push(10),
+            ClientMessage::SwitchWeapon { seq, weapon_idx } => {
+                buf.push(11);
+                buf.extend_from_slice(&seq.to_be_bytes());
+                buf.push(*weapon_idx);
+            }
+        }
+
+        buf
+
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+
+        let mut reader: Reader = Reader::new(buf);
+
+        let msg: Self = match reader.u8().map_err(|_| DecodeError::Empty)? {
+            0 => ClientMessage::Hello { version: reader.u16()? },
+            1 => ClientMessage::ListMatches,
+            2 => ClientMessage::CreateMatch,
+            3 => ClientMessage::JoinMatch { match_id: reader.u32()? },
+            4 => ClientMessage::Input { seq: reader.u32()?, input: Input::from_u8(reader.u8()?)? },
+            5 => ClientMessage::Click { seq: reader.u32()?, view_tick: reader.u32()?, x: reader.u16()?, y: reader.u16()? },
+            6 => ClientMessage::Ack { tick: reader.u32()? },
+            7 => ClientMessage::SpectateMatch { match_id: reader.u32()?, room_idx: reader.u16()? },
+            8 => ClientMessage::WatchRoom { room_idx: reader.u16()? },
+            9 => ClientMessage::AddBot { difficulty: reader.u8()? },
+            10 => ClientMessage::RemoveBot,
+            11 => ClientMessage::SwitchWeapon { seq: reader.u32()?, weapon_idx: reader.u8()? },
+            tag => return Err(DecodeError::UnknownTag(tag)),
+        };
+
+        reader.finish()?;
+
+        Ok(msg)
+
+    }
+
+}
+
+impl ServerMessage {
+
+    pub const SNAPSHOT_TAG: u8 = 5;
+    pub const SPECTATOR_SNAPSHOT_TAG: u8 = 8;
+
+    pub fn encode(&self) -> Vec<u8> {
+
+        let mut buf: Vec<u8> = Vec::new();
+
+        match self {
+            ServerMessage::Welcome { version } => {
+                buf.push(0);
+                buf.extend_from_slice(&version.to_be_bytes());
+            }
+            ServerMessage::Rejected { reason } => {
+                buf.push(1);
+                encode_string(&mut buf, reason);
+            }
+            ServerMessage::MatchList { match_ids } => {
+                buf.push(2);
+                encode_list(&mut buf, match_ids, |match_id, buf| buf.extend_from_slice(&match_id.to_be_bytes()));
+            }
+            ServerMessage::Joined { match_id, user_idx } => {
+                buf.push(3);
+                buf.extend_from_slice(&match_id.to_be_bytes());
+                buf.push(*user_idx);
+            }
+            ServerMessage::JoinFailed => buf.push(4),
+            ServerMessage::Snapshot { snapshot, footer } => {
+                snapshot.encode(&mut buf);
+                footer.encode(&mut buf);
+            }
+            ServerMessage::RoomGeometry(room_geometry) => {
+                buf.push(6);
+                buf.extend_from_slice(&room_geometry.room_idx.to_be_bytes());
+                encode_list(&mut buf, &room_geometry.platforms, Rect::encode);
+                encode_list(&mut buf, &room_geometry.doors, Rect::encode);
+                encode_list(&mut buf, &room_geometry.walls, Rect::encode);
+                encode_list(&mut buf, &room_geometry.solids, Rect::encode);
+            }
+            ServerMessage::Spectating { match_id } => {
+                buf.push(7);
+                buf.extend_from_slice(&match_id.to_be_bytes());
+            }
+            ServerMessage::SpectatorSnapshot { snapshot } => {
+                snapshot.encode(&mut buf);
+                buf[0] = Self::SPECTATOR_SNAPSHOT_TAG;
+            }
+            ServerMessage::MatchPhase { phase, end_tick } => {
+                buf.push(9);
+                buf.push(*phase);
+                encode_optional(&mut buf, end_tick, |end_tick, buf| buf.extend_from_slice(&end_tick.to_be_bytes()));
+            }
+            ServerMessage::Scoreboard { winner_user_idx, scores } => {
+                buf.push(10);
+                encode_optional(&mut buf, winner_user_idx, |user_idx, buf| buf.push(*user_idx));
+                encode_list(&mut buf, scores, Score::encode);
+            }
+            ServerMessage::Killed { respawn_tick } => {
+                buf.push(11);
+                buf.extend_from_slice(&respawn_tick.to_be_bytes());
+            }
+            ServerMessage::TickStats { tick, tick_rate, ticks, skipped, mean_tick_us, max_tick_us, max_late_us } => {
+                buf.push(12);
+                buf.extend_from_slice(&tick.to_be_bytes());
+                buf.extend_from_slice(&tick_rate.to_be_bytes());
+                buf.extend_from_slice(&ticks.to_be_bytes());
+                buf.extend_from_slice(&skipped.to_be_bytes());
+                buf.extend_from_slice(&mean_tick_us.to_be_bytes());
+                buf.extend_from_slice(&max_tick_us.to_be_bytes());
+                buf.extend_from_slice(&max_late_us.to_be_bytes());
+            }
+            ServerMessage::Kicked { reason } => {
+                buf.push(13);
+                encode_string(&mut buf, reason);
+            }
+        }
+
+        buf
+
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodeError> {
+
+        let mut reader: Reader = Reader::new(buf);
+
+        let msg: Self = match reader.u8().map_err(|_| DecodeError::Empty)? {
+            0 => ServerMessage::Welcome { version: reader.u16()? },
+            1 => ServerMessage::Rejected { reason: reader.string()? },
+            2 => ServerMessage::MatchList { match_ids: reader.list(Reader::u32)? },
+            3 => ServerMessage::Joined { match_id: reader.u32()?, user_idx: reader.u8()? },
+            4 => ServerMessage::JoinFailed,
+            Self::SNAPSHOT_TAG => ServerMessage::Snapshot {
+                snapshot: Snapshot::decode_body(&mut reader)?,
+                footer: Footer::decode(&mut reader)?,
+            },
+            6 => ServerMessage::RoomGeometry(RoomGeometry {
+                room_idx: reader.u16()?,
+                platforms: reader.list(Rect::decode)?,
+                doors: reader.list(Rect::decode)?,
+                walls: reader.list(Rect::decode)?,
+                solids: reader.list(Rect::decode)?,
+            }),
+            7 => ServerMessage::Spectating { match_id: reader.u32()? },
+            Self::SPECTATOR_SNAPSHOT_TAG => ServerMessage::SpectatorSnapshot { snapshot: Snapshot::decode_body(&mut reader)? },
+            9 => ServerMessage::MatchPhase { phase: reader.u8()?, end_tick: reader.optional(Reader::u32)? },
+            10 => ServerMessage::Scoreboard { winner_user_idx: reader.optional(Reader::u8)?, scores: reader.list(Score::decode)? },
+            11 => ServerMessage::Killed { respawn_tick: reader.u32()? },
+            12 => ServerMessage::TickStats {
+                tick: reader.u32()?,
+                tick_rate: reader.u16()?,
+                ticks: reader.u16()?,
+                skipped: reader.u16()?,
+                mean_tick_us: reader.u32()?,
+                max_tick_us: reader.u32()?,
+                max_late_us: reader.u32()?,
+            },
+            13 => ServerMessage::Kicked { reason: reader.string()? },
+            tag => return Err(DecodeError::UnknownTag(tag)),
+        };
+
+        reader.finish()?;
+
+        Ok(msg)
+
+    }
+
+}
+
+impl Rect {
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(self.width);
+        buf.push(self.height);
+        buf.extend_from_slice(&self.x.to_be_bytes());
+        buf.extend_from_slice(&self.y.to_be_bytes());
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(Self {
+            width: reader.u8()?,
+            height: reader.u8()?,
+            x: reader.u16()?,
+            y: reader.u16()?,
+        })
+    }
+
+}
+
+impl Line {
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.origin_x.to_be_bytes());
+        buf.extend_from_slice(&self.origin_y.to_be_bytes());
+        buf.extend_from_slice(&self.end_x.to_be_bytes());
+        buf.extend_from_slice(&self.end_y.to_be_bytes());
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(Self {
+            origin_x: reader.u16()?,
+            origin_y: reader.u16()?,
+            end_x: reader.u16()?,
+            end_y: reader.u16()?,
+        })
+    }
+
+}
+
+impl Point {
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.x.to_be_bytes());
+        buf.extend_from_slice(&self.y.to_be_bytes());
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(Self {
+            x: reader.u16()?,
+            y: reader.u16()?,
+        })
+    }
+
+}
+
+impl UserState {
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(self.user_idx);
+        self.rect.encode(buf);
+        buf.push(self.protected as u8);
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(Self {
+            user_idx: reader.u8()?,
+            rect: Rect::decode(reader)?,
+            protected: reader.u8()? != 0,
+        })
+    }
+
+}
+
+impl Snapshot {
+
+    // writes the snapshot tag and body, the game appends a footer per user or swaps the tag for spectators
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+
+        buf.push(ServerMessage::SNAPSHOT_TAG);
+        buf.extend_from_slice(&self.tick.to_be_bytes());
+
+        match self.baseline_tick {
+            None => buf.push(0),
+            Some(baseline_tick) => {
+                buf.push(1);
+                buf.extend_from_slice(&baseline_tick.to_be_bytes());
+            }
+        }
+
+        encode_list(buf, &self.users, UserState::encode);
+        encode_list(buf, &self.removed_users, |user_idx, buf| buf.push(*user_idx));
+        encode_list(buf, &self.bullet_paths, Line::encode);
+        encode_list(buf, &self.projectiles, Point::encode);
+        encode_list(buf, &self.moving_platforms, Rect::encode);
+
+    }
+
+    fn decode_body(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(Self {
+            tick: reader.u32()?,
+            baseline_tick: match reader.u8()? {
+                0 => None,
+                _ => Some(reader.u32()?),
+            },
+            users: reader.list(UserState::decode)?,
+            removed_users: reader.list(Reader::u8)?,
+            bullet_paths: reader.list(Line::decode)?,
+            projectiles: reader.list(Point::decode)?,
+            moving_platforms: reader.list(Rect::decode)?,
+        })
+    }
+
+}
+
+impl Movement {
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.x.to_be_bytes());
+        buf.extend_from_slice(&self.y.to_be_bytes());
+        buf.extend_from_slice(&self.dx.to_be_bytes());
+        buf.extend_from_slice(&self.dy.to_be_bytes());
+        buf.push(self.coyote_ticks);
+        buf.push(self.jump_buffer_ticks);
+        buf.extend_from_slice(&self.stun_ticks.to_be_bytes());
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(Self {
+            x: reader.f32()?,
+            y: reader.f32()?,
+            dx: reader.f32()?,
+            dy: reader.f32()?,
+            coyote_ticks: reader.u8()?,
+            jump_buffer_ticks: reader.u8()?,
+            stun_ticks: reader.u16()?,
+        })
+    }
+
+}
+
+impl Loadout {
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(self.weapon_idx);
+        buf.extend_from_slice(&self.ammo.to_be_bytes());
+        buf.extend_from_slice(&self.cooldown_ticks.to_be_bytes());
+        buf.extend_from_slice(&self.reload_ticks.to_be_bytes());
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(Self {
+            weapon_idx: reader.u8()?,
+            ammo: reader.u16()?,
+            cooldown_ticks: reader.u16()?,
+            reload_ticks: reader.u16()?,
+        })
+    }
+
+}
+
+impl Reveal {
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.room_idx.to_be_bytes());
+        self.rect.encode(buf);
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(Self {
+            room_idx: reader.u16()?,
+            rect: Rect::decode(reader)?,
+        })
+    }
+
+}
+
+impl Footer {
+
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(self.user_idx);
+        buf.push(self.target_user_idx);
+        buf.extend_from_slice(&self.last_input_seq.to_be_bytes());
+        self.movement.encode(buf);
+        self.loadout.encode(buf);
+        encode_optional(buf, &self.target_reveal, Reveal::encode);
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(Self {
+            user_idx: reader.u8()?,
+            target_user_idx: reader.u8()?,
+            last_input_seq: reader.u32()?,
+            movement: Movement::decode(reader)?,
+            loadout: Loadout::decode(reader)?,
+            target_reveal: reader.optional(Reveal::decode)?,
+        })
+    }
+
+}
+
+impl Score {
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(self.user_idx);
+        buf.extend_from_slice(&self.points.to_be_bytes());
+        buf.extend_from_slice(&self.kills.to_be_bytes());
+        buf.extend_from_slice(&self.deaths.to_be_bytes());
+        buf.extend_from_slice(&self.wrong_target_hits.to_be_bytes());
+        buf.extend_from_slice(&self.best_streak.to_be_bytes());
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(Self {
+            user_idx: reader.u8()?,
+            points: reader.i16()?,
+            kills: reader.u16()?,
+            deaths: reader.u16()?,
+            wrong_target_hits: reader.u16()?,
+            best_streak: reader.u16()?,
+        })
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn snapshot(baseline_tick: Option<u32>) -> Snapshot {
+        Snapshot {
+            tick: 120,
+            baseline_tick,
+            users: vec![
+                UserState { user_idx: 0, rect: Rect { width: 10, height: 10, x: 40, y: 300 }, protected: false },
+                UserState { user_idx: 3, rect: Rect { width: 10, height: 10, x: 65535, y: 0 }, protected: true },
+            ],
+            removed_users: vec![1, 2],
+            bullet_paths: vec![Line { origin_x: 40, origin_y: 300, end_x: 240, end_y: 280 }],
+            projectiles: vec![Point { x: 100, y: 200 }],
+            moving_platforms: vec![Rect { width: 255, height: 4, x: 500, y: 120 }],
+        }
+    }
+
+    fn footer(target_reveal: Option<Reveal>) -> Footer {
+        Footer {
+            user_idx: 0,
+            target_user_idx: 3,
+            last_input_seq: 77,
+            movement: Movement { x: 40.5, y: 300.25, dx: -1.5, dy: 3.0, coyote_ticks: 2, jump_buffer_ticks: 0, stun_ticks: 60 },
+            loadout: Loadout { weapon_idx: 1, ammo: 0, cooldown_ticks: 40, reload_ticks: 0 },
+            target_reveal,
+        }
+    }
+
+    #[test]
+    fn client_messages_round_trip() {
+
+        let msgs: Vec<ClientMessage> = vec![
+            ClientMessage::Hello { version: VERSION },
+            ClientMessage::ListMatches,
+            ClientMessage::CreateMatch,
+            ClientMessage::JoinMatch { match_id: u32::MAX },
+            ClientMessage::Input { seq: 1, input: Input::UpStart },
+            ClientMessage::Input { seq: 2, input: Input::UpEnd },
+            ClientMessage::Input { seq: 3, input: Input::DownStart },
+            ClientMessage::Input { seq: 4, input: Input::DownEnd },
+            ClientMessage::Input { seq: 5, input: Input::LeftStart },
+            ClientMessage::Input { seq: 6, input: Input::LeftEnd },
+            ClientMessage::Input { seq: 7, input: Input::RightStart },
+            ClientMessage::Input { seq: 8, input: Input::RightEnd },
+            ClientMessage::Input { seq: 9, input: Input::Reload },
+            ClientMessage::Click { seq: 10, view_tick: 118, x: 240, y: 280 },
+            ClientMessage::Ack { tick: 120 },
+            ClientMessage::SpectateMatch { match_id: 4, room_idx: 1 },
+            ClientMessage::WatchRoom { room_idx: 2 },
+            ClientMessage::AddBot { difficulty: 2 },
+            ClientMessage::RemoveBot,
+            ClientMessage::SwitchWeapon { seq: 11, weapon_idx: 1 },
+        ];
+
+        for msg in msgs {
+            assert_eq!(ClientMessage::decode(&msg.encode()), Ok(msg));
+        }
+
+    }
+
+    #[test]
+    fn server_messages_round_trip() {
+
+        let msgs: Vec<ServerMessage> = vec![
+            ServerMessage::Welcome { version: VERSION },
+            ServerMessage::Rejected { reason: String::from("unsupported version, ünïcode") },
+            ServerMessage::MatchList { match_ids: vec![] },
+            ServerMessage::MatchList { match_ids: vec![0, 7, u32::MAX] },
+            ServerMessage::Joined { match_id: 7, user_idx: 4 },
+            ServerMessage::JoinFailed,
+            ServerMessage::Snapshot { snapshot: snapshot(None), footer: footer(None) },
+            ServerMessage::Snapshot {
+                snapshot: snapshot(Some(118)),
+                footer: footer(Some(Reveal { room_idx: 1, rect: Rect { width: 10, height: 10, x: 80, y: 90 } })),
+            },
+            ServerMessage::RoomGeometry(RoomGeometry {
+                room_idx: 1,
+                platforms: vec![Rect { width: 100, height: 4, x: 0, y: 380 }],
+                doors: vec![Rect { width: 10, height: 30, x: 790, y: 350 }],
+                walls: vec![],
+                solids: vec![Rect { width: 20, height: 20, x: 300, y: 300 }, Rect { width: 1, height: 1, x: 0, y: 0 }],
+            }),
+            ServerMessage::Spectating { match_id: 7 },
+            ServerMessage::SpectatorSnapshot { snapshot: snapshot(None) },
+            ServerMessage::SpectatorSnapshot { snapshot: snapshot(Some(119)) },
+            ServerMessage::MatchPhase { phase: 0, end_tick: None },
+            ServerMessage::MatchPhase { phase: 1, end_tick: Some(18_750) },
+            ServerMessage::Scoreboard { winner_user_idx: None, scores: vec![] },
+            ServerMessage::Scoreboard {
+                winner_user_idx: Some(3),
+                scores: vec![
+                    Score { user_idx: 3, points: 10, kills: 11, deaths: 2, wrong_target_hits: 1, best_streak: 6 },
+                    Score { user_idx: 0, points: -4, kills: 0, deaths: 9, wrong_target_hits: 4, best_streak: 0 },
+                ],
+            },
+            ServerMessage::Killed { respawn_tick: 240 },
+            ServerMessage::TickStats { tick: 600, tick_rate: 60, ticks: 60, skipped: 1, mean_tick_us: 150, max_tick_us: 900, max_late_us: 2000 },
+            ServerMessage::Kicked { reason: String::from("too slow") },
+        ];
+
+        for msg in msgs {
+            assert_eq!(ServerMessage::decode(&msg.encode()), Ok(msg));
+        }
+
+    }
+
+    #[test]
+    fn decode_errors() {
+
+        assert_eq!(ClientMessage::decode(&[]), Err(DecodeError::Empty));
+        assert_eq!(ServerMessage::decode(&[]), Err(DecodeError::Empty));
+
+        assert_eq!(ClientMessage::decode(&[12]), Err(DecodeError::UnknownTag(12)));
+        assert_eq!(ServerMessage::decode(&[255]), Err(DecodeError::UnknownTag(255)));
+        assert_eq!(ClientMessage::decode(&[4, 0, 0, 0, 1, 9]), Err(DecodeError::UnknownInput(9)));
+
+        // every message cut short anywhere after its tag
+        let click: Vec<u8> = ClientMessage::Click { seq: 1, view_tick: 2, x: 3, y: 4 }.encode();
+        let snapshot: Vec<u8> = ServerMessage::Snapshot { snapshot: snapshot(Some(118)), footer: footer(None) }.encode();
+
+        for len in 1..click.len() {
+            assert_eq!(ClientMessage::decode(&click[..len]), Err(DecodeError::Truncated));
+        }
+
+        for len in 1..snapshot.len() {
+            assert_eq!(ServerMessage::decode(&snapshot[..len]), Err(DecodeError::Truncated));
+        }
+
+        let mut ack: Vec<u8> = ClientMessage::Ack { tick: 1 }.encode();
+
+        ack.extend_from_slice(&[0, 0]);
+
+        assert_eq!(ClientMessage::decode(&ack), Err(DecodeError::TrailingBytes(2)));
+
+        let mut killed: Vec<u8> = ServerMessage::Killed { respawn_tick: 1 }.encode();
+
+        killed.push(0);
+
+        assert_eq!(ServerMessage::decode(&killed), Err(DecodeError::TrailingBytes(1)));
+
+        assert_eq!(ServerMessage::decode(&[13, 2, 0xc3, 0x28]), Err(DecodeError::InvalidUtf8));
+
+    }
+
+}
@@ -3,6 +3,10 @@ use crate::entity;
 pub enum IntersectionVariant {
     User(usize),
     Platform,
+    Wall,
+    Solid,
+    // (door idx)
+    Door(usize),
 }
 
 pub struct Intersection {
@@ -10,6 +14,7 @@ pub struct Intersection {
     pub distance: f32,
 }
 
+#[derive(Clone, Copy)]
 pub struct Ray {
     pub origin_x: f32,
     pub origin_y: f32,
@@ -19,7 +24,8 @@ pub struct Ray {
 
 impl Ray {
 
-    pub fn from_entity_and_position(entity: &entity::Entity, x: f32, y: f32) -> Self {
+    // None when the position is the entity's center (or not a number), there's no direction to go in
+    pub fn from_entity_and_position(entity: &entity::Entity, x: f32, y: f32) -> Option<Self> {
 
         let origin_x: f32 = entity.x + entity.width * 0.5;
         let origin_y: f32 = entity.y + entity.height * 0.5;
@@ -32,16 +38,31 @@ impl Ray {
         let direction_x: f32 = distance_x / magnitude;
         let direction_y: f32 = distance_y / magnitude; 
 
-        return Self {
+        if !direction_x.is_finite() || !direction_y.is_finite() {
+            return None;
+        }
+
+        Some(Self {
             origin_x,
             origin_y,
             direction_x, 
             direction_y,
-        }
+        })
 
     }
 
     pub fn intersection(&self, entity: &entity::Entity) -> Option<f32> {
+        self.slab(entity).map(|(_, max_distance)| max_distance)
+    }
+
+    // how far along the ray it enters the entity, 0 when it starts inside, a ray leaving from its edge doesn't enter it
+    pub fn entry(&self, entity: &entity::Entity) -> Option<f32> {
+        self.slab(entity)
+            .filter(|(_, max_distance)| *max_distance > 0.0)
+            .map(|(min_distance, _)| min_distance.max(0.0))
+    }
+
+    fn slab(&self, entity: &entity::Entity) -> Option<(f32, f32)> {
 
         let mut min_distance: f32 = f32::NEG_INFINITY;
         let mut max_distance: f32 = f32::INFINITY;
@@ -74,7 +95,7 @@ impl Ray {
             return None;
         }
 
-        return Some(max_distance);
+        Some((min_distance, max_distance))
 
     }
 
@@ -0,0 +1,598 @@
+//! Match recordings, written by `Game` when the server runs with `--record <dir>` and played back with
+//! `server replay <file> [tick]`.
+//!
+//! Everything that can change a `Simulation` is recorded with the tick it happened on, so replaying the events
+//! in order through a fresh simulation reproduces the match exactly. Integers are big endian like `protocol`.
+//!
+//! | header     | body                                             |
+//! |------------|--------------------------------------------------|
+//! | magic      | b"RPLY"                                          |
+//! | version    | u16                                              |
+//! | seed       | u64                                              |
+//! | rooms      | len: u32, rooms as json                          |
+//! | rules      | len: u32, `rules::Rules` as json                 |
+//! | physics    | len: u32, `user::Physics` as json                |
+//! | max rewind | u32, `Simulation::max_rewind_ticks`               |
+//!
+//! followed by events until the end of the file, each `tick: u32, tag: u8, body`
+//!
+//! | tag | event    | body                                        |
+//! |-----|----------|---------------------------------------------|
+//! | 0   | Join     | user_idx: u8                                |
+//! | 1   | Leave    | user_idx: u8                                |
+//! | 2   | Input    | user_idx: u8, input: u8 (see `protocol::Input`) |
+//! | 3   | Click    | user_idx: u8, view_tick: u32, x: f32, y: f32 |
+//! | 4   | Checksum | checksum: u64 of every occupied room's render buffer |
+//! | 5   | SwitchWeapon | user_idx: u8, weapon_idx: u8                |
+//! | 6   | HotReload | len: u32, rooms as json, len: u32, `user::Physics` as json |
+//! | 7   | Reserve  | user_idx: u8                                |
+//! | 8   | Release  | user_idx: u8                                |
+//!
+//! A user kicked (or whose channel closed) leaves and has its idx reserved until its client disconnects, which
+//! releases it. Joins, leaves and inputs are stamped with the tick the simulation was on when they arrived, inputs are applied
+//! by the following tick. A checksum is stamped with the tick it was taken after. Reloads happen between ticks, stamped
+//! with the tick the simulation was on.
+
+use std::io::Write;
+use std::sync::Arc;
+use crate::{ protocol, room, rules, simulation, user };
+
+const MAGIC: &[u8; 4] = b"RPLY";
+pub const VERSION: u16 = 8;
+
+#[derive(Debug, Clone)]
+pub enum Event {
+    Join(usize),
+    Leave(usize),
+    Input(simulation::Input),
+    Checksum(u64),
+    HotReload(Arc<Vec<room::Room>>, user::Physics),
+    Reserve(usize),
+    Release(usize),
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(std::path::PathBuf, std::io::Error),
+    Decode(protocol::DecodeError),
+    BadMagic,
+    UnsupportedVersion(u16),
+    Rooms(serde_json::Error),
+    Rules(serde_json::Error),
+    Physics(serde_json::Error),
+    // the simulation gave a joining user a different idx than it did while recording
+    JoinMismatch { tick: u32, recorded: usize, replayed: Option<usize> },
+}
+
+impl std::fmt::Display for ReplayError {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+
+        match self {
+            ReplayError::Io(path, err) => write!(f, "failed to access {}: {}", path.display(), err),
+            ReplayError::Decode(err) => write!(f, "failed to decode replay: {}", err),
+            ReplayError::BadMagic => write!(f, "not a replay file"),
+            ReplayError::UnsupportedVersion(version) => write!(f, "unsupported replay version {}, expected {}", version, VERSION),
+            ReplayError::Rooms(err) => write!(f, "failed to parse recorded rooms: {}", err),
+            ReplayError::Rules(err) => write!(f, "failed to parse recorded rules: {}", err),
+            ReplayError::Physics(err) => write!(f, "failed to parse recorded physics: {}", err),
+            ReplayError::JoinMismatch { tick, recorded, replayed } => write!(f, "tick {}: user joined as {} but replayed as {:?}", tick, recorded, replayed),
+        }
+
+    }
+
+}
+
+impl From<protocol::DecodeError> for ReplayError {
+    fn from(err: protocol::DecodeError) -> Self {
+        ReplayError::Decode(err)
+    }
+}
+
+pub struct Recorder {
+    path: std::path::PathBuf,
+    writer: std::io::BufWriter<std::fs::File>,
+}
+
+impl Recorder {
+
+    pub fn create(path: std::path::PathBuf, rooms: &[room::Room], rules: &rules::Rules, physics: &user::Physics, max_rewind_ticks: u32, seed: u64) -> Result<Self, ReplayError> {
+
+        let file: std::fs::File = std::fs::File::create(&path).map_err(|err| ReplayError::Io(path.clone(), err))?;
+
+        let rooms_json: Vec<u8> = serde_json::to_vec(rooms).map_err(ReplayError::Rooms)?;
+        let rules_json: Vec<u8> = serde_json::to_vec(rules).map_err(ReplayError::Rules)?;
+        let physics_json: Vec<u8> = serde_json::to_vec(physics).map_err(ReplayError::Physics)?;
+
+        let mut buf: Vec<u8> = Vec::with_capacity(30 + rooms_json.len() + rules_json.len() + physics_json.len());
+
+        buf.extend_from_slice(MAGIC);
+        buf.extend_from_slice(&VERSION.to_be_bytes());
+        buf.extend_from_slice(&seed.to_be_bytes());
+        buf.extend_from_slice(&(rooms_json.len() as u32).to_be_bytes());
+        buf.extend_from_slice(&rooms_json);
+        buf.extend_from_slice(&(rules_json.len() as u32).to_be_bytes());
+        buf.extend_from_slice(&rules_json);
+        buf.extend_from_slice(&(physics_json.len() as u32).to_be_bytes());
+        buf.extend_from_slice(&physics_json);
+        buf.extend_from_slice(&max_rewind_ticks.to_be_bytes());
+
+        let mut recorder: Self = Self {
+            path,
+            writer: std::io::BufWriter::new(file),
+        };
+
+        recorder.write(&buf)?;
+
+        Ok(recorder)
+
+    }
+
+    pub fn record(&mut self, tick: u32, event: Event) -> Result<(), ReplayError> {
+
+        let mut buf: Vec<u8> = Vec::with_capacity(18);
+
+        buf.extend_from_slice(&tick.to_be_bytes());
+
+        event.encode(&mut buf);
+
+        self.write(&buf)
+
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<(), ReplayError> {
+        self.writer.write_all(buf).map_err(|err| ReplayError::Io(self.path.clone(), err))
+    }
+
+}
+
+impl Event {
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+
+        match *self {
+            Event::Join(idx) => buf.extend_from_slice(&[0, idx as u8]),
+            Event::Leave(idx) => buf.extend_from_slice(&[1, idx as u8]),
+            Event::Input(simulation::Input::Click(idx, view_tick, x, y)) => {
+                buf.extend_from_slice(&[3, idx as u8]);
+                buf.extend_from_slice(&view_tick.to_be_bytes());
+                buf.extend_from_slice(&x.to_be_bytes());
+                buf.extend_from_slice(&y.to_be_bytes());
+            }
+            Event::Input(simulation::Input::SwitchWeapon(idx, weapon_idx)) => buf.extend_from_slice(&[5, idx as u8, weapon_idx as u8]),
+            Event::Input(input) => {
+
+                let (idx, input): (usize, u8) = match input {
+                    simulation::Input::UpStart(idx) => (idx, 0),
+                    simulation::Input::UpEnd(idx) => (idx, 1),
+                    simulation::Input::DownStart(idx) => (idx, 2),
+                    simulation::Input::DownEnd(idx) => (idx, 3),
+                    simulation::Input::LeftStart(idx) => (idx, 4),
+                    simulation::Input::LeftEnd(idx) => (idx, 5),
+                    simulation::Input::RightStart(idx) => (idx, 6),
+                    simulation::Input::RightEnd(idx) => (idx, 7),
+                    simulation::Input::Reload(idx) => (idx, 8),
+                    simulation::Input::Click(..) | simulation::Input::SwitchWeapon(..) => unreachable!(),
+                };
+
+                buf.extend_from_slice(&[2, idx as u8, input]);
+
+            }
+            Event::Checksum(checksum) => {
+                buf.push(4);
+                buf.extend_from_slice(&checksum.to_be_bytes());
+            }
+            Event::HotReload(ref rooms, physics) => {
+
+                // plain structs always serialize
+                let rooms_json: Vec<u8> = serde_json::to_vec(&**rooms).expect("rooms as json");
+                let physics_json: Vec<u8> = serde_json::to_vec(&physics).expect("physics as json");
+
+                buf.push(6);
+                buf.extend_from_slice(&(rooms_json.len() as u32).to_be_bytes());
+                buf.extend_from_slice(&rooms_json);
+                buf.extend_from_slice(&(physics_json.len() as u32).to_be_bytes());
+                buf.extend_from_slice(&physics_json);
+
+            }
+            Event::Reserve(idx) => buf.extend_from_slice(&[7, idx as u8]),
+            Event::Release(idx) => buf.extend_from_slice(&[8, idx as u8]),
+        }
+
+    }
+
+    fn decode(reader: &mut protocol::Reader) -> Result<Self, ReplayError> {
+
+        let tag: u8 = reader.u8()?;
+
+        let event: Event = match tag {
+            0 => Event::Join(reader.u8()? as usize),
+            1 => Event::Leave(reader.u8()? as usize),
+            2 => {
+
+                let idx: usize = reader.u8()? as usize;
+
+                let input: simulation::Input = match reader.u8()? {
+                    0 => simulation::Input::UpStart(idx),
+                    1 => simulation::Input::UpEnd(idx),
+                    2 => simulation::Input::DownStart(idx),
+                    3 => simulation::Input::DownEnd(idx),
+                    4 => simulation::Input::LeftStart(idx),
+                    5 => simulation::Input::LeftEnd(idx),
+                    6 => simulation::Input::RightStart(idx),
+                    7 => simulation::Input::RightEnd(idx),
+                    8 => simulation::Input::Reload(idx),
+                    input => return Err(protocol::DecodeError::UnknownInput(input).into()),
+                };
+
+                Event::Input(input)
+
+            }
+            3 => Event::Input(simulation::Input::Click(reader.u8()? as usize, reader.u32()?, reader.f32()?, reader.f32()?)),
+            4 => Event::Checksum(reader.u64()?),
+            5 => Event::Input(simulation::Input::SwitchWeapon(reader.u8()? as usize, reader.u8()? as usize)),
+            6 => {
+
+                let rooms_len: usize = reader.u32()? as usize;
+                let rooms: Vec<room::Room> = serde_json::from_slice(reader.bytes(rooms_len)?).map_err(ReplayError::Rooms)?;
+
+                let physics_len: usize = reader.u32()? as usize;
+                let physics: user::Physics = serde_json::from_slice(reader.bytes(physics_len)?).map_err(ReplayError::Physics)?;
+
+                Event::HotReload(Arc::new(rooms), physics)
+
+            }
+            7 => Event::Reserve(reader.u8()? as usize),
+            8 => Event::Release(reader.u8()? as usize),
+            tag => return Err(protocol::DecodeError::UnknownTag(tag).into()),
+        };
+
+        Ok(event)
+
+    }
+
+}
+
+// fnv-1a over the keyframe of every occupied room, stable across builds unlike std's hasher
+pub fn checksum(simulation: &simulation::Simulation) -> u64 {
+
+    let mut hash: u64 = 0xcbf29ce484222325;
+
+    for buf in render(simulation).iter().map(|(_, buf)| buf) {
+        for byte in buf {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    hash
+
+}
+
+// (room_idx, encoded snapshot) for every occupied room, in room order
+pub fn render(simulation: &simulation::Simulation) -> Vec<(usize, Vec<u8>)> {
+
+    let mut rooms: Vec<(usize, Vec<u8>)> = Vec::new();
+
+    for room_idx in 0..simulation.rooms.len() {
+
+        if !simulation.users.iter().flatten().any(|user| user.room_idx == room_idx) {
+            continue;
+        }
+
+        let mut buf: Vec<u8> = Vec::new();
+
+        simulation.render(room_idx).encode(&mut buf);
+
+        rooms.push((room_idx, buf));
+
+    }
+
+    rooms
+
+}
+
+pub struct Replay {
+    pub seed: u64,
+    pub rooms: Vec<room::Room>,
+    pub rules: rules::Rules,
+    pub physics: user::Physics,
+    pub max_rewind_ticks: u32,
+    pub events: Vec<(u32, Event)>,
+}
+
+impl Replay {
+
+    pub fn load(path: &std::path::Path) -> Result<Self, ReplayError> {
+
+        let buf: Vec<u8> = std::fs::read(path).map_err(|err| ReplayError::Io(path.to_path_buf(), err))?;
+
+        let mut reader: protocol::Reader = protocol::Reader::new(&buf);
+
+        if reader.bytes(MAGIC.len())? != MAGIC {
+            return Err(ReplayError::BadMagic);
+        }
+
+        let version: u16 = reader.u16()?;
+
+        if version != VERSION {
+            return Err(ReplayError::UnsupportedVersion(version));
+        }
+
+        let seed: u64 = reader.u64()?;
+
+        let rooms_len: usize = reader.u32()? as usize;
+
+        let rooms: Vec<room::Room> = serde_json::from_slice(reader.bytes(rooms_len)?).map_err(ReplayError::Rooms)?;
+
+        let rules_len: usize = reader.u32()? as usize;
+
+        let rules: rules::Rules = serde_json::from_slice(reader.bytes(rules_len)?).map_err(ReplayError::Rules)?;
+
+        let physics_len: usize = reader.u32()? as usize;
+
+        let physics: user::Physics = serde_json::from_slice(reader.bytes(physics_len)?).map_err(ReplayError::Physics)?;
+
+        let max_rewind_ticks: u32 = reader.u32()?;
+
+        let mut events: Vec<(u32, Event)> = Vec::new();
+
+        while !reader.is_empty() {
+            events.push((reader.u32()?, Event::decode(&mut reader)?));
+        }
+
+        Ok(Self { seed, rooms, rules, physics, max_rewind_ticks, events })
+
+    }
+
+}
+
+// re-runs a recording, reporting every tick whose render buffers differ from the recorded ones,
+// and printing the render buffers at show_tick if given
+// how a replay compared to its recording
+#[derive(Debug)]
+pub struct Playback {
+    pub ticks: u32,
+    pub checksums: u32,
+    pub mismatches: u32,
+}
+
+pub fn play(path: &std::path::Path, show_tick: Option<u32>) -> Result<Playback, ReplayError> {
+
+    let replay: Replay = Replay::load(path)?;
+
+    let mut simulation: simulation::Simulation = simulation::Simulation::new(Arc::new(replay.rooms), replay.rules, replay.physics, replay.seed);
+    let mut inputs: Vec<simulation::Input> = Vec::new();
+
+    simulation.max_rewind_ticks = replay.max_rewind_ticks;
+
+    let mut checksums: u32 = 0;
+    let mut mismatches: u32 = 0;
+
+    for (tick, event) in replay.events {
+
+        while simulation.tick < tick {
+
+            simulation.tick(inputs.drain(..));
+            simulation.events.clear();
+
+            if show_tick == Some(simulation.tick) {
+                for (room_idx, _) in render(&simulation) {
+                    println!("room {}: {:?}", room_idx, simulation.render(room_idx));
+                }
+            }
+
+        }
+
+        match event {
+            Event::Join(recorded) => {
+
+                let replayed: Option<usize> = simulation.join();
+
+                if replayed != Some(recorded) {
+                    return Err(ReplayError::JoinMismatch { tick, recorded, replayed });
+                }
+
+            }
+            Event::Leave(idx) => simulation.leave(idx),
+            Event::Reserve(idx) => simulation.reserve(idx),
+            Event::Release(idx) => simulation.release(idx),
+            Event::Input(input) => inputs.push(input),
+            Event::HotReload(rooms, physics) => {
+                simulation.hot_reload(rooms, physics);
+            }
+            Event::Checksum(recorded) => {
+
+                checksums += 1;
+
+                if checksum(&simulation) != recorded {
+                    mismatches += 1;
+                    println!("tick {}: render buffers differ from the recording", tick);
+                }
+
+            }
+        }
+
+    }
+
+    if simulation.phase == rules::Phase::Ended {
+        println!("match ended, winner: {:?}", simulation.winner());
+    }
+
+    Ok(Playback { ticks: simulation.tick, checksums, mismatches })
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    // unique per test so they can run in parallel
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("server-test-{}-{}.replay", std::process::id(), name))
+    }
+
+    fn rooms() -> Vec<room::Room> {
+        room::load_rooms(std::path::Path::new("rooms")).expect("the default rooms load")
+    }
+
+    #[test]
+    fn events_round_trip() {
+
+        let events: Vec<Event> = vec![
+            Event::Join(3),
+            Event::Leave(254),
+            Event::Input(simulation::Input::UpStart(1)),
+            Event::Input(simulation::Input::UpEnd(1)),
+            Event::Input(simulation::Input::DownStart(1)),
+            Event::Input(simulation::Input::DownEnd(1)),
+            Event::Input(simulation::Input::LeftStart(1)),
+            Event::Input(simulation::Input::LeftEnd(1)),
+            Event::Input(simulation::Input::RightStart(1)),
+            Event::Input(simulation::Input::RightEnd(1)),
+            Event::Input(simulation::Input::Reload(1)),
+            Event::Input(simulation::Input::Click(2, 118, 240.5, -3.25)),
+            Event::Checksum(0xcbf29ce484222325),
+            Event::Input(simulation::Input::SwitchWeapon(2, 1)),
+            Event::HotReload(Arc::new(rooms()), user::Physics::default()),
+            Event::Reserve(4),
+            Event::Release(4),
+        ];
+
+        for event in events {
+
+            let mut buf: Vec<u8> = Vec::new();
+
+            event.encode(&mut buf);
+
+            let mut reader: protocol::Reader = protocol::Reader::new(&buf);
+            let decoded: Event = Event::decode(&mut reader).expect("event decodes");
+
+            assert!(reader.is_empty());
+            // rooms aren't comparable, but everything in them is printed
+            assert_eq!(format!("{:?}", decoded), format!("{:?}", event));
+
+        }
+
+    }
+
+    #[test]
+    fn unknown_event_tag_is_rejected() {
+
+        let mut reader: protocol::Reader = protocol::Reader::new(&[9, 0]);
+
+        assert!(matches!(Event::decode(&mut reader), Err(ReplayError::Decode(protocol::DecodeError::UnknownTag(9)))));
+
+    }
+
+    #[test]
+    fn other_files_and_versions_are_rejected() {
+
+        let path: std::path::PathBuf = temp_path("rejected");
+
+        std::fs::write(&path, b"NOPE\0\x08").unwrap();
+
+        assert!(matches!(Replay::load(&path), Err(ReplayError::BadMagic)));
+
+        let mut buf: Vec<u8> = MAGIC.to_vec();
+
+        buf.extend_from_slice(&(VERSION - 1).to_be_bytes());
+
+        std::fs::write(&path, &buf).unwrap();
+
+        assert!(matches!(Replay::load(&path), Err(ReplayError::UnsupportedVersion(version)) if version == VERSION - 1));
+
+        std::fs::remove_file(&path).unwrap();
+
+    }
+
+    #[test]
+    fn recorded_match_replays_without_mismatches() {
+
+        let path: std::path::PathBuf = temp_path("recorded");
+        let seed: u64 = 11;
+
+        let mut simulation: simulation::Simulation = simulation::Simulation::new(Arc::new(rooms()), rules::Rules::default(), user::Physics::default(), seed);
+
+        simulation.max_rewind_ticks = 15;
+
+        let mut recorder: Recorder = Recorder::create(path.clone(), &simulation.rooms, &simulation.rules, &simulation.physics, 15, seed).unwrap();
+
+        // recorded in the order a game records them, see Game::record
+        for _ in 0..4 {
+            let idx: usize = simulation.join().unwrap();
+            recorder.record(simulation.tick, Event::Join(idx)).unwrap();
+        }
+
+        for tick in 0..600 {
+
+            let mut inputs: Vec<simulation::Input> = Vec::new();
+
+            for (idx, user) in simulation.users.iter().enumerate() {
+
+                let user: &user::User = match user {
+                    Some(user) => user,
+                    None => continue,
+                };
+
+                match (tick + idx as u32 * 11) % 60 {
+                    0 => inputs.push(simulation::Input::RightStart(idx)),
+                    20 => inputs.push(simulation::Input::UpStart(idx)),
+                    25 => inputs.push(simulation::Input::UpEnd(idx)),
+                    30 => inputs.push(simulation::Input::RightEnd(idx)),
+                    40 => inputs.push(simulation::Input::LeftStart(idx)),
+                    55 => inputs.push(simulation::Input::LeftEnd(idx)),
+                    _ => (),
+                }
+
+                if tick % 10 == idx as u32 {
+                    inputs.push(simulation::Input::Click(idx, tick.saturating_sub(5), user.dynamic_entity.entity.x + 50.0, user.dynamic_entity.entity.y - 20.0));
+                }
+
+            }
+
+            for input in &inputs {
+                recorder.record(simulation.tick, Event::Input(*input)).unwrap();
+            }
+
+            match tick {
+                200 => {
+                    simulation.leave(2);
+                    simulation.reserve(2);
+                    recorder.record(simulation.tick, Event::Leave(2)).unwrap();
+                    recorder.record(simulation.tick, Event::Reserve(2)).unwrap();
+                }
+                250 => {
+                    simulation.release(2);
+                    recorder.record(simulation.tick, Event::Release(2)).unwrap();
+                }
+                300 => {
+                    let rooms: Arc<Vec<room::Room>> = Arc::new(rooms());
+                    simulation.hot_reload(rooms.clone(), simulation.physics);
+                    recorder.record(simulation.tick, Event::HotReload(rooms, simulation.physics)).unwrap();
+                }
+                _ => (),
+            }
+
+            simulation.tick(inputs);
+            simulation.events.clear();
+
+            recorder.record(simulation.tick, Event::Checksum(checksum(&simulation))).unwrap();
+
+        }
+
+        // flushes the file
+        drop(recorder);
+
+        let playback: Playback = play(&path, None).unwrap();
+
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(playback.ticks, 600);
+        assert_eq!(playback.checksums, 600);
+        assert_eq!(playback.mismatches, 0);
+
+    }
+
+}
@@ -1,79 +1,475 @@
-use crate::{ bullet, entity, };
+use serde::{ Deserialize, Serialize };
+use crate::{ bullet, entity, kinematic, projectile, protocol, snapshot, user };
 
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Room {
     pub gravity: f32,
     pub bounds: Bounds,
-    pub platforms: &'static [entity::Entity],
-    pub doors: &'static [Door],
+    pub platforms: Vec<entity::Entity>,
+    // stop bullets and projectiles but not users, who walk in front of them
+    #[serde(default)]
+    pub walls: Vec<entity::Entity>,
+    // block users from every side and stop shots
+    #[serde(default)]
+    pub solids: Vec<entity::Entity>,
+    // stood on from above like platforms, users standing on one move with it
+    #[serde(default)]
+    pub moving_platforms: Vec<kinematic::MovingPlatform>,
+    pub doors: Vec<Door>,
+    // where users (re)spawn, a room without any is never spawned in
+    pub spawns: Vec<Spawn>,
 }
 
 pub struct RoomMut {
-    pub bullets: Vec<bullet::Bullet>, 
+    pub bullets: Vec<bullet::Bullet>,
     pub bullet_paths: Vec<bullet::BulletPath>,
+    pub projectiles: Vec<projectile::Projectile>,
+    // indexed like Room::moving_platforms
+    pub moving_platforms: Vec<kinematic::State>,
+    pub hitboxes: snapshot::History<bullet::Hitboxes>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Door {
-    pub entity: entity::Entity, 
+    pub entity: entity::Entity,
     pub room_idx: usize,
     pub door_idx: usize,
 }
 
+// top left corner of a spawning user
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Spawn {
+    pub x: f32,
+    pub y: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Bounds {
     pub x_max: f32,
     pub y_max: f32,
 }
 
-pub type RoomsMut = [RoomMut; ROOM_COUNT];
-
-pub const ROOM_COUNT: usize = 2;
-
-pub const ROOMS: [Room; ROOM_COUNT] = [
-    Room {
-        gravity: 1.5,
-        bounds: Bounds { x_max: u8::MAX as f32, y_max: u8::MAX as f32 },
-        platforms: &[
-            entity::Entity { x: 70.0, y: 245.0, width: 50.0, height: 3.0 },
-            entity::Entity { x: 100.0, y: 220.0, width: 50.0, height: 3.0 },
-            entity::Entity { x: 170.0, y: 230.0, width: 50.0, height: 3.0 },
-        ],
-        doors: &[
-            Door {
-                entity: entity::Entity { x: 250.0, y: 225.0, width: 5.0, height: 30.0 },
-                room_idx: 1,
-                door_idx: 0,
+pub type RoomsMut = Vec<RoomMut>;
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(std::path::PathBuf, std::io::Error),
+    Parse(std::path::PathBuf, serde_json::Error),
+    InvalidFileName(std::path::PathBuf),
+    MissingRoom(usize),
+    NoRooms,
+    DanglingDoor { room_idx: usize, door_idx: usize },
+    Overlap { room_idx: usize, entity: entity::Entity, other: entity::Entity },
+    OutOfBounds { room_idx: usize, entity: entity::Entity },
+    NegativeSize { room_idx: usize, entity: entity::Entity },
+    // wider or taller than a protocol::Rect can hold
+    TooLarge { room_idx: usize, entity: entity::Entity },
+    NoSpawns,
+    // a user spawning there would be outside the room or inside its geometry
+    BlockedSpawn { room_idx: usize, spawn_idx: usize },
+    InvalidPath { room_idx: usize, moving_platform_idx: usize },
+}
+
+impl std::fmt::Display for LoadError {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+
+        match self {
+            LoadError::Io(path, err) => write!(f, "failed to read {}: {}", path.display(), err),
+            LoadError::Parse(path, err) => write!(f, "failed to parse {}: {}", path.display(), err),
+            LoadError::InvalidFileName(path) => write!(f, "room file name must be its room index: {}", path.display()),
+            LoadError::MissingRoom(room_idx) => write!(f, "missing room file for room {}", room_idx),
+            LoadError::NoRooms => write!(f, "no room files found"),
+            LoadError::DanglingDoor { room_idx, door_idx } => write!(f, "door {} in room {} links to a door that does not exist", door_idx, room_idx),
+            LoadError::Overlap { room_idx, entity, other } => write!(f, "room {} has overlapping geometry: {:?} and {:?}", room_idx, entity, other),
+            LoadError::OutOfBounds { room_idx, entity } => write!(f, "room {} has geometry outside of its bounds: {:?}", room_idx, entity),
+            LoadError::NegativeSize { room_idx, entity } => write!(f, "room {} has geometry with a negative width or height: {:?}", room_idx, entity),
+            LoadError::TooLarge { room_idx, entity } => write!(f, "room {} has geometry larger than {}x{}: {:?}", room_idx, protocol::MAX_RECT_SIZE, protocol::MAX_RECT_SIZE, entity),
+            LoadError::NoSpawns => write!(f, "no room has a spawn point"),
+            LoadError::BlockedSpawn { room_idx, spawn_idx } => write!(f, "spawn {} in room {} is out of bounds or inside geometry", spawn_idx, room_idx),
+            LoadError::InvalidPath { room_idx, moving_platform_idx } => write!(f, "moving platform {} in room {} has a path it can't follow", moving_platform_idx, room_idx),
+        }
+
+    }
+
+}
+
+impl Room {
+
+    fn validate(&self, room_idx: usize, rooms: &[Room]) -> Result<(), LoadError> {
+
+        for (door_idx, door) in self.doors.iter().enumerate() {
+
+            let linked_door_exists: bool = rooms
+                .get(door.room_idx)
+                .is_some_and(|room| door.door_idx < room.doors.len());
+
+            if !linked_door_exists {
+                return Err(LoadError::DanglingDoor { room_idx, door_idx });
+            }
+
+        }
+
+        let entities: Vec<&entity::Entity> = self.platforms
+            .iter()
+            .chain(self.walls.iter())
+            .chain(self.solids.iter())
+            .chain(self.doors.iter().map(|door| &door.entity))
+            .collect();
+
+        for (idx, entity) in entities.iter().enumerate() {
+
+            // would pass as in bounds and overlapping nothing
+            if !has_size(entity) {
+                return Err(LoadError::NegativeSize { room_idx, entity: (*entity).clone() });
+            }
+
+            if !self.bounds.contains(entity) {
+                return Err(LoadError::OutOfBounds { room_idx, entity: (*entity).clone() });
+            }
+
+            if !fits_rect(entity) {
+                return Err(LoadError::TooLarge { room_idx, entity: (*entity).clone() });
+            }
+
+            if let Some(other) = entities[idx + 1..].iter().find(|other| entity.overlaps(other)) {
+                return Err(LoadError::Overlap { room_idx, entity: (*entity).clone(), other: (*other).clone() });
+            }
+
+        }
+
+        // moving platforms pass through everything else, they only have to stay in the room
+        for (moving_platform_idx, moving_platform) in self.moving_platforms.iter().enumerate() {
+
+            if !moving_platform.path.is_valid() {
+                return Err(LoadError::InvalidPath { room_idx, moving_platform_idx });
+            }
+
+            let platform: entity::Entity = moving_platform.entity_at(0);
+
+            if !has_size(&platform) {
+                return Err(LoadError::NegativeSize { room_idx, entity: platform });
+            }
+
+            if !fits_rect(&platform) {
+                return Err(LoadError::TooLarge { room_idx, entity: platform });
+            }
+
+            let extent: entity::Entity = moving_platform.extent();
+
+            if !self.bounds.contains(&extent) {
+                return Err(LoadError::OutOfBounds { room_idx, entity: extent });
             }
-        ],
-    },
-    Room {
-        gravity: 1.5,
-        bounds: Bounds { x_max: 255.0, y_max: 255.0 },
-        platforms: &[
-            entity::Entity { x: 70.0, y: 245.0, width: 50.0, height: 3.0 },
-            //entity::Entity { x: 100.0, y: 220.0, width: 50.0, height: 3.0 },
-            //entity::Entity { x: 170.0, y: 230.0, width: 50.0, height: 3.0 },
-        ],
-        doors: &[
-            Door {
-                entity: entity::Entity { x: 0.0, y: 225.0, width: 5.0, height: 30.0 },
-                room_idx: 0,
-                door_idx: 0,
+
+        }
+
+        for (spawn_idx, spawn) in self.spawns.iter().enumerate() {
+
+            let entity: entity::Entity = spawn.to_entity();
+
+            if !self.bounds.contains(&entity) || entities.iter().any(|other| entity.overlaps(other)) {
+                return Err(LoadError::BlockedSpawn { room_idx, spawn_idx });
             }
-        ],
+
+        }
+
+        Ok(())
+
+    }
+
+    // a user there can't move, they're outside the room or inside a solid
+    pub fn traps(&self, entity: &entity::Entity) -> bool {
+        !self.bounds.contains(entity) || self.solids.iter().any(|solid| entity.overlaps(solid))
+    }
+
+}
+
+impl Bounds {
+
+    // entirely inside, touching the edges counts
+    pub fn contains(&self, entity: &entity::Entity) -> bool {
+        entity.x >= 0.0 &&
+        entity.y >= 0.0 &&
+        entity.x + entity.width <= self.x_max &&
+        entity.y + entity.height <= self.y_max
+    }
+
+    pub fn to_entity(&self) -> entity::Entity {
+        entity::Entity {
+            x: 0.0,
+            y: 0.0,
+            width: self.x_max,
+            height: self.y_max,
+        }
+    }
+
+}
+
+impl Spawn {
+
+    // the space a user takes up when spawning here
+    pub fn to_entity(self) -> entity::Entity {
+        entity::Entity {
+            x: self.x,
+            y: self.y,
+            width: user::User::SIZE,
+            height: user::User::SIZE,
+        }
     }
-];
+
+}
 
 impl RoomMut {
-    const fn new() -> Self {
-        RoomMut { 
-            bullets: Vec::new(), 
-            bullet_paths: Vec::new(), 
+    fn new() -> Self {
+        RoomMut {
+            bullets: Vec::new(),
+            bullet_paths: Vec::new(),
+            projectiles: Vec::new(),
+            moving_platforms: Vec::new(),
+            hitboxes: snapshot::History::new(),
         }
     }
 }
 
-pub const fn rooms_mut() -> RoomsMut {
-    [
-        RoomMut::new(),
-        RoomMut::new(),
-    ]
+// room files are named by their index (0.json, 1.json, ...) so door links stay stable
+pub fn load_rooms(dir: &std::path::Path) -> Result<Vec<Room>, LoadError> {
+
+    let read_dir: std::fs::ReadDir = std::fs::read_dir(dir).map_err(|err| LoadError::Io(dir.to_path_buf(), err))?;
+
+    let mut files: Vec<(usize, std::path::PathBuf)> = Vec::new();
+
+    for dir_entry in read_dir {
+
+        let path: std::path::PathBuf = dir_entry.map_err(|err| LoadError::Io(dir.to_path_buf(), err))?.path();
+
+        if path.extension().is_none_or(|extension| extension != "json") {
+            continue;
+        }
+
+        let room_idx: usize = match path.file_stem().and_then(|stem| stem.to_str()).and_then(|stem| stem.parse().ok()) {
+            Some(room_idx) => room_idx,
+            None => return Err(LoadError::InvalidFileName(path)),
+        };
+
+        files.push((room_idx, path));
+
+    }
+
+    if files.is_empty() {
+        return Err(LoadError::NoRooms);
+    }
+
+    files.sort_by_key(|(room_idx, _)| *room_idx);
+
+    let mut rooms: Vec<Room> = Vec::with_capacity(files.len());
+
+    for (expected_room_idx, (room_idx, path)) in files.into_iter().enumerate() {
+
+        if room_idx != expected_room_idx {
+            return Err(LoadError::MissingRoom(expected_room_idx));
+        }
+
+        let file: std::fs::File = std::fs::File::open(&path).map_err(|err| LoadError::Io(path.clone(), err))?;
+
+        let room: Room = serde_json::from_reader(std::io::BufReader::new(file)).map_err(|err| LoadError::Parse(path, err))?;
+
+        rooms.push(room);
+
+    }
+
+    for (room_idx, room) in rooms.iter().enumerate() {
+        room.validate(room_idx, &rooms)?;
+    }
+
+    if rooms.iter().all(|room| room.spawns.is_empty()) {
+        return Err(LoadError::NoSpawns);
+    }
+
+    Ok(rooms)
+
+}
+
+pub fn rooms_mut(room_count: usize) -> RoomsMut {
+    (0..room_count).map(|_| RoomMut::new()).collect()
+}
+
+fn has_size(entity: &entity::Entity) -> bool {
+    entity.width >= 0.0 && entity.height >= 0.0
+}
+
+fn fits_rect(entity: &entity::Entity) -> bool {
+    entity.width <= protocol::MAX_RECT_SIZE as f32 && entity.height <= protocol::MAX_RECT_SIZE as f32
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn entity(x: f32, y: f32, width: f32, height: f32) -> entity::Entity {
+        entity::Entity { x, y, width, height }
+    }
+
+    // an empty 200x200 room with a spawn in the corner, every test breaks it a different way
+    fn room() -> Room {
+        Room {
+            gravity: 1.5,
+            bounds: Bounds { x_max: 200.0, y_max: 200.0 },
+            platforms: Vec::new(),
+            walls: Vec::new(),
+            solids: Vec::new(),
+            moving_platforms: Vec::new(),
+            doors: Vec::new(),
+            spawns: vec![Spawn { x: 0.0, y: 0.0 }],
+        }
+    }
+
+    fn validate(room: Room) -> Result<(), LoadError> {
+        let rooms: Vec<Room> = vec![room];
+        rooms[0].validate(0, &rooms)
+    }
+
+    fn moving_platform(from: kinematic::Point, to: kinematic::Point, ticks: u32) -> kinematic::MovingPlatform {
+        kinematic::MovingPlatform { width: 30.0, height: 3.0, path: kinematic::Path::Linear { from, to, ticks } }
+    }
+
+    #[test]
+    fn default_rooms_are_valid() {
+        assert!(load_rooms(std::path::Path::new("rooms")).is_ok());
+    }
+
+    #[test]
+    fn geometry_touching_the_bounds_is_valid() {
+
+        let mut room: Room = room();
+
+        room.solids.push(entity(190.0, 150.0, 10.0, 50.0));
+
+        assert!(validate(room).is_ok());
+
+    }
+
+    #[test]
+    fn dangling_door_is_rejected() {
+
+        let mut room: Room = room();
+
+        room.doors.push(Door { entity: entity(190.0, 150.0, 10.0, 50.0), room_idx: 0, door_idx: 1 });
+
+        assert!(matches!(validate(room), Err(LoadError::DanglingDoor { room_idx: 0, door_idx: 0 })));
+
+    }
+
+    #[test]
+    fn out_of_bounds_geometry_is_rejected() {
+
+        let mut room: Room = room();
+
+        room.platforms.push(entity(180.0, 100.0, 30.0, 3.0));
+
+        assert!(matches!(validate(room), Err(LoadError::OutOfBounds { .. })));
+
+    }
+
+    #[test]
+    fn negative_width_is_rejected() {
+
+        let mut room: Room = room();
+
+        room.walls.push(entity(100.0, 100.0, -20.0, 10.0));
+
+        assert!(matches!(validate(room), Err(LoadError::NegativeSize { .. })));
+
+    }
+
+    #[test]
+    fn negative_height_is_rejected() {
+
+        let mut room: Room = room();
+
+        room.solids.push(entity(100.0, 100.0, 20.0, -10.0));
+
+        assert!(matches!(validate(room), Err(LoadError::NegativeSize { .. })));
+
+    }
+
+    #[test]
+    fn too_large_geometry_is_rejected() {
+
+        let mut room: Room = room();
+
+        room.bounds = Bounds { x_max: 1000.0, y_max: 200.0 };
+        room.platforms.push(entity(0.0, 100.0, protocol::MAX_RECT_SIZE as f32 + 1.0, 3.0));
+
+        assert!(matches!(validate(room), Err(LoadError::TooLarge { .. })));
+
+    }
+
+    #[test]
+    fn overlapping_geometry_is_rejected() {
+
+        let mut room: Room = room();
+
+        room.platforms.push(entity(50.0, 100.0, 50.0, 3.0));
+        room.solids.push(entity(90.0, 90.0, 20.0, 20.0));
+
+        assert!(matches!(validate(room), Err(LoadError::Overlap { .. })));
+
+    }
+
+    #[test]
+    fn moving_platform_without_a_lap_is_rejected() {
+
+        let mut room: Room = room();
+
+        room.moving_platforms.push(moving_platform(kinematic::Point { x: 50.0, y: 100.0 }, kinematic::Point { x: 100.0, y: 100.0 }, 0));
+
+        assert!(matches!(validate(room), Err(LoadError::InvalidPath { room_idx: 0, moving_platform_idx: 0 })));
+
+    }
+
+    #[test]
+    fn moving_platform_with_negative_size_is_rejected() {
+
+        let mut room: Room = room();
+
+        room.moving_platforms.push(kinematic::MovingPlatform { width: -30.0, ..moving_platform(kinematic::Point { x: 50.0, y: 100.0 }, kinematic::Point { x: 100.0, y: 100.0 }, 60) });
+
+        assert!(matches!(validate(room), Err(LoadError::NegativeSize { .. })));
+
+    }
+
+    #[test]
+    fn moving_platform_leaving_the_room_is_rejected() {
+
+        let mut room: Room = room();
+
+        room.moving_platforms.push(moving_platform(kinematic::Point { x: 50.0, y: 100.0 }, kinematic::Point { x: 180.0, y: 100.0 }, 60));
+
+        assert!(matches!(validate(room), Err(LoadError::OutOfBounds { .. })));
+
+    }
+
+    #[test]
+    fn spawn_outside_the_room_is_rejected() {
+
+        let mut room: Room = room();
+
+        room.spawns.push(Spawn { x: 195.0, y: 0.0 });
+
+        assert!(matches!(validate(room), Err(LoadError::BlockedSpawn { room_idx: 0, spawn_idx: 1 })));
+
+    }
+
+    #[test]
+    fn spawn_inside_geometry_is_rejected() {
+
+        let mut room: Room = room();
+
+        room.solids.push(entity(0.0, 0.0, 20.0, 20.0));
+
+        assert!(matches!(validate(room), Err(LoadError::BlockedSpawn { room_idx: 0, spawn_idx: 0 })));
+
+    }
+
 }
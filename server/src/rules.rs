@@ -0,0 +1,162 @@
+use serde::{ Deserialize, Serialize };
+use crate::{ protocol, simulation, weapon };
+
+// how a match is played and won, ticks are simulation ticks (scheduler::Config::tick_rate a second)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Rules {
+    // warmup ends this long after enough users are in the match, nothing is scored during warmup
+    pub warmup_ticks: u32,
+    pub min_players: usize,
+    // first to this many points wins
+    pub score_limit: Option<i32>,
+    // the leader when time runs out wins
+    pub time_limit_ticks: Option<u32>,
+    // killed users are out for the rest of the match, the last one left wins
+    pub last_hunter_standing: bool,
+    // how long killed users wait to come back
+    pub respawn_ticks: u32,
+    // how long (re)spawned users can't be killed
+    pub spawn_protection_ticks: u32,
+    // for shooting anyone but your target
+    pub wrong_target: Penalties,
+    // what shooting your own hunter does, the hunter is only penalized as a wrong target with Penalized
+    pub self_defence: SelfDefence,
+    // every user carries all of them, starting with the first
+    pub weapons: Vec<weapon::Weapon>,
+}
+
+// every penalty that isn't zero or false is applied to the shooter
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Penalties {
+    // the shooter can't run, jump or shoot
+    pub stun_ticks: u32,
+    // only taken while the match is live
+    pub points: i32,
+    // the shooter's hunter is sent where the shooter is, wherever they are
+    pub reveal_ticks: u32,
+    // the shooter dies (and respawns) as if they had been shot, nobody is credited with the kill
+    pub kill: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum SelfDefence {
+    // the hunter takes the hit without anything happening
+    Allowed,
+    // the hunter dies as if they had been hunted down, but keeps hunting the shooter after respawning
+    Kill,
+    Penalized,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Phase {
+    Warmup,
+    Live,
+    Ended,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Score {
+    pub points: i32,
+    pub kills: u32,
+    pub deaths: u32,
+    pub wrong_target_hits: u32,
+    pub streak: u32,
+    pub best_streak: u32,
+    pub eliminated: bool,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            warmup_ticks: 600,
+            min_players: 2,
+            score_limit: Some(10),
+            time_limit_ticks: Some(18_750),
+            last_hunter_standing: false,
+            respawn_ticks: 120,
+            spawn_protection_ticks: 90,
+            wrong_target: Penalties::default(),
+            self_defence: SelfDefence::Allowed,
+            weapons: vec![weapon::Weapon::PISTOL, weapon::Weapon::BOW],
+        }
+    }
+}
+
+impl Default for Penalties {
+    fn default() -> Self {
+        Self {
+            stun_ticks: 60,
+            points: 1,
+            reveal_ticks: 180,
+            kill: false,
+        }
+    }
+}
+
+impl Rules {
+
+    // the first field a match couldn't be played with and what it should be
+    pub fn invalid_field(&self) -> Option<(&'static str, &'static str)> {
+
+        let fields: [(&'static str, bool, &'static str); 8] = [
+            ("rules.min_players", self.min_players >= 1 && self.min_players <= simulation::MAX_PLAYERS, "between 1 and 255"),
+            // a match with a limit of 0 would end as soon as it goes live, no limit is none
+            ("rules.score_limit", self.score_limit.is_none_or(|score_limit| score_limit > 0), "above 0 or none"),
+            ("rules.time_limit_ticks", self.time_limit_ticks.is_none_or(|time_limit_ticks| time_limit_ticks > 0), "above 0 or none"),
+            // longer than ~18 minutes at the default tick rate is out for the match, which is last_hunter_standing
+            ("rules.respawn_ticks", self.respawn_ticks <= u16::MAX as u32, "at most 65535"),
+            // a penalty that gives points is a reward
+            ("rules.wrong_target.points", self.wrong_target.points >= 0, "0 or above"),
+            // stuns are sent as u16, and a reveal as long is already for the rest of any match
+            ("rules.wrong_target.stun_ticks", self.wrong_target.stun_ticks <= u16::MAX as u32, "at most 65535"),
+            ("rules.wrong_target.reveal_ticks", self.wrong_target.reveal_ticks <= u16::MAX as u32, "at most 65535"),
+            // weapon idxs are sent as u8, and unarmed users could never hit their target
+            ("rules.weapons", !self.weapons.is_empty() && self.weapons.len() <= u8::MAX as usize + 1 && self.weapons.iter().all(weapon::Weapon::is_valid), "between 1 and 256 weapons with a range above 0, a spread of 0 or above and a projectile speed above 0"),
+        ];
+
+        fields.iter().find(|(_, valid, _)| !valid).map(|(field, _, expected)| (*field, *expected))
+
+    }
+
+}
+
+impl Phase {
+
+    pub fn to_u8(self) -> u8 {
+        match self {
+            Phase::Warmup => 0,
+            Phase::Live => 1,
+            Phase::Ended => 2,
+        }
+    }
+
+}
+
+impl Score {
+
+    pub fn kill(&mut self) {
+        self.points += 1;
+        self.kills += 1;
+        self.streak += 1;
+        self.best_streak = self.best_streak.max(self.streak);
+    }
+
+    pub fn death(&mut self) {
+        self.deaths += 1;
+        self.streak = 0;
+    }
+
+    pub fn to_protocol(self, user_idx: usize) -> protocol::Score {
+        protocol::Score {
+            user_idx: user_idx as u8,
+            points: self.points.clamp(i16::MIN as i32, i16::MAX as i32) as i16,
+            kills: self.kills.min(u16::MAX as u32) as u16,
+            deaths: self.deaths.min(u16::MAX as u32) as u16,
+            wrong_target_hits: self.wrong_target_hits.min(u16::MAX as u32) as u16,
+            best_streak: self.best_streak.min(u16::MAX as u32) as u16,
+        }
+    }
+
+}
@@ -0,0 +1,174 @@
+use serde::{ Deserialize, Serialize };
+use tokio::time::{ Duration, Instant };
+use crate::{ metrics, protocol };
+
+// how often a match ticks and what happens when it falls behind, the simulation is tuned for DEFAULT_TICK_RATE,
+// rules and physics count ticks so a different rate speeds up or slows down the whole match
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    // ticks per second
+    pub tick_rate: u32,
+    // most ticks run back to back to catch up, deadlines missed beyond that are skipped
+    pub max_catch_up_ticks: u32,
+    pub missed_tick_behavior: MissedTickBehavior,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MissedTickBehavior {
+    // runs the missed ticks back to back (up to max_catch_up_ticks), keeping the match on its original schedule
+    Burst,
+    // runs one tick and schedules the next a full tick from now, the match falls behind wall time
+    Delay,
+    // runs one tick and drops the missed ones, the next tick is at the next deadline on the original schedule
+    Skip,
+}
+
+// hands out tick numbers at fixed deadlines from the start, so time spent handling messages or ticking doesn't drift them
+pub struct Scheduler {
+    config: Config,
+    tick_dt: Duration,
+    // deadline of tick number next_tick
+    next_deadline: Instant,
+    next_tick: u64,
+    stats: Stats,
+}
+
+// gathered over a window of tick_rate scheduled ticks (a second when keeping up)
+#[derive(Debug, Default)]
+struct Stats {
+    ticks: u32,
+    skipped: u32,
+    total_tick_time: Duration,
+    max_tick_time: Duration,
+    max_late: Duration,
+    window_ticks: u32,
+    last_tick: u64,
+}
+
+pub const DEFAULT_TICK_RATE: u32 = 60;
+
+impl Default for Config {
+
+    fn default() -> Self {
+        Self {
+            tick_rate: DEFAULT_TICK_RATE,
+            max_catch_up_ticks: 5,
+            missed_tick_behavior: MissedTickBehavior::Burst,
+        }
+    }
+
+}
+
+impl MissedTickBehavior {
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "burst" => Some(MissedTickBehavior::Burst),
+            "delay" => Some(MissedTickBehavior::Delay),
+            "skip" => Some(MissedTickBehavior::Skip),
+            _ => None,
+        }
+    }
+
+}
+
+impl Scheduler {
+
+    pub fn new(config: Config) -> Self {
+
+        let tick_dt: Duration = Duration::from_secs(1) / config.tick_rate.max(1);
+
+        Self {
+            config,
+            tick_dt,
+            next_deadline: Instant::now() + tick_dt,
+            next_tick: 0,
+            stats: Stats::default(),
+        }
+
+    }
+
+    pub fn next_deadline(&self) -> Instant {
+        self.next_deadline
+    }
+
+    // the tick numbers to run now that the next deadline has passed, in order, at least one and never more than
+    // max_catch_up_ticks, ticks given up on are counted as skipped
+    pub fn due(&mut self) -> std::ops::Range<u64> {
+
+        let now: Instant = Instant::now();
+        let late: Duration = now.saturating_duration_since(self.next_deadline);
+
+        self.stats.max_late = self.stats.max_late.max(late);
+
+        // the deadline just passed is due along with every one missed since
+        let missed: u64 = (late.as_nanos() / self.tick_dt.as_nanos()) as u64;
+
+        let (run, skipped) = match self.config.missed_tick_behavior {
+            MissedTickBehavior::Burst => {
+                let run: u64 = (missed + 1).min(self.config.max_catch_up_ticks.max(1) as u64);
+                (run, missed + 1 - run)
+            }
+            MissedTickBehavior::Delay | MissedTickBehavior::Skip => (1, missed),
+        };
+
+        // the oldest deadlines are the ones given up on
+        let first_tick: u64 = self.next_tick + skipped;
+
+        self.next_tick += run + skipped;
+        self.stats.skipped += skipped as u32;
+
+        metrics::METRICS.ticks_skipped.add(skipped);
+        self.stats.window_ticks += (run + skipped) as u32;
+
+        self.next_deadline = match self.config.missed_tick_behavior {
+            MissedTickBehavior::Delay => now + self.tick_dt,
+            MissedTickBehavior::Burst | MissedTickBehavior::Skip => self.next_deadline + self.tick_dt * (missed + 1) as u32,
+        };
+
+        first_tick..first_tick + run
+
+    }
+
+    // how long running the tick took
+    pub fn record(&mut self, tick: u64, tick_time: Duration) {
+        self.stats.last_tick = tick;
+        self.stats.ticks += 1;
+        self.stats.total_tick_time += tick_time;
+        self.stats.max_tick_time = self.stats.max_tick_time.max(tick_time);
+
+        metrics::METRICS.tick_seconds.observe(tick_time.as_secs_f64());
+
+        if tick_time > self.tick_dt {
+            metrics::METRICS.tick_overruns.inc();
+        }
+    }
+
+    // the stats of the last window once it's full, starting the next one
+    pub fn take_stats(&mut self) -> Option<protocol::ServerMessage> {
+
+        if self.stats.window_ticks < self.config.tick_rate {
+            return None;
+        }
+
+        let stats: Stats = std::mem::take(&mut self.stats);
+
+        Some(protocol::ServerMessage::TickStats {
+            tick: stats.last_tick as u32,
+            tick_rate: self.config.tick_rate.min(u16::MAX as u32) as u16,
+            ticks: stats.ticks.min(u16::MAX as u32) as u16,
+            skipped: stats.skipped.min(u16::MAX as u32) as u16,
+            mean_tick_us: micros(stats.total_tick_time / stats.ticks.max(1)),
+            max_tick_us: micros(stats.max_tick_time),
+            max_late_us: micros(stats.max_late),
+        })
+
+    }
+
+}
+
+fn micros(duration: Duration) -> u32 {
+    duration.as_micros().min(u32::MAX as u128) as u32
+}
@@ -0,0 +1,883 @@
+use std::sync::Arc;
+use rand::{ Rng, SeedableRng };
+use crate::{ bullet, kinematic, projectile, protocol, ray, room, rules, slice, spawn, user, weapon };
+use slice::IterPlucked;
+
+// the world of a single match, stepped one tick at a time with no knowledge of sockets or timers,
+// the same seed, rooms and sequence of joins/leaves/inputs always produce the same world
+pub struct Simulation {
+    pub tick: u32,
+    pub users: Vec<Option<user::User>>,
+    pub rooms: Arc<Vec<room::Room>>,
+    pub rooms_mut: room::RoomsMut,
+    pub rules: rules::Rules,
+    pub physics: user::Physics,
+    // joins past this many users are turned away, up to MAX_PLAYERS
+    pub max_players: usize,
+    // how far back a shot can be resolved, less than snapshot::History::LEN
+    pub max_rewind_ticks: u32,
+    pub phase: rules::Phase,
+    // when the warmup countdown or the time limit runs out, None while waiting for players or without a time limit
+    pub phase_end_tick: Option<u32>,
+    // indexed like users, eliminated users keep their score after leaving users
+    pub scores: Vec<Option<rules::Score>>,
+    // killed users waiting to come back, they have no user until then
+    pub respawns: Vec<Respawn>,
+    // drained by whoever runs the simulation
+    pub events: Vec<Event>,
+    // idxs of users who left before their client said so, joins skip them until they're released
+    pub reserved: Vec<usize>,
+    rng: rand_chacha::ChaCha8Rng,
+}
+
+// what happened during a tick that isn't part of the rendered world
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    // the phase changed or its end tick moved
+    Phase,
+    // the user was killed while playing last hunter standing and is out of the match
+    Eliminated { user_idx: usize, room_idx: usize },
+    Killed { user_idx: usize, respawn_tick: u32 },
+    Fired { user_idx: usize },
+    // a shot reached a user, whether or not it hurt them
+    Hit { user_idx: usize, hit_user_idx: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Respawn {
+    pub user_idx: usize,
+    pub killed_tick: u32,
+    pub respawn_tick: u32,
+    // where they died
+    pub room_idx: usize,
+    pub killer_idx: Option<usize>,
+    // who to hunt after respawning if they're still around, anyone otherwise
+    pub target_user_idx: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Input {
+    UpStart(usize),
+    UpEnd(usize),
+    DownStart(usize),
+    DownEnd(usize),
+    LeftStart(usize),
+    LeftEnd(usize),
+    RightStart(usize),
+    RightEnd(usize),
+    // (user idx, view tick, x, y)
+    Click(usize, u32, f32, f32),
+    Reload(usize),
+    // (user idx, weapon idx)
+    SwitchWeapon(usize, usize),
+}
+
+pub const MAX_PLAYERS: usize = u8::MAX as usize;
+
+impl Simulation {
+
+    pub fn new(rooms: Arc<Vec<room::Room>>, rules: rules::Rules, physics: user::Physics, seed: u64) -> Self {
+
+        let mut simulation: Self = Self {
+            tick: 0,
+            users: Vec::with_capacity(MAX_PLAYERS),
+            rooms_mut: room::rooms_mut(rooms.len()),
+            rooms,
+            rules,
+            physics,
+            max_players: MAX_PLAYERS,
+            max_rewind_ticks: 0,
+            phase: rules::Phase::Warmup,
+            phase_end_tick: None,
+            scores: Vec::with_capacity(MAX_PLAYERS),
+            respawns: Vec::new(),
+            events: Vec::new(),
+            reserved: Vec::new(),
+            rng: rand_chacha::ChaCha8Rng::seed_from_u64(seed),
+        };
+
+        simulation.move_platforms();
+
+        simulation
+
+    }
+
+    // None when the match is full
+    pub fn join(&mut self) -> Option<usize> {
+
+        // an eliminated or respawning user's slot is kept for their score
+        let idx: usize = match (0..self.users.len()).position(|idx| self.users[idx].is_none() && self.scores[idx].is_none() && !self.reserved.contains(&idx)) {
+            Some(idx) => idx,
+            None if self.users.len() < self.max_players.min(MAX_PLAYERS) => {
+                self.users.push(None);
+                self.scores.push(None);
+                self.users.len() - 1
+            }
+            None => return None,
+        };
+
+        let target_user_idx: usize = user::User::get_target_idx(&mut self.users, idx, &mut self.rng);
+
+        self.spawn(idx, target_user_idx, None);
+        self.scores[idx] = Some(rules::Score::default());
+
+        Some(idx)
+
+    }
+
+    pub fn leave(&mut self, idx: usize) {
+
+        // shots still flying would be credited to whoever joins into idx next
+        for room_mut in &mut self.rooms_mut {
+            room_mut.bullets.retain(|bullet| bullet.user_idx != idx);
+            room_mut.projectiles.retain(|projectile| projectile.user_idx != idx);
+        }
+
+        let respawning: bool = self.respawns.iter().any(|respawn| respawn.user_idx == idx);
+
+        self.respawns.retain(|respawn| respawn.user_idx != idx);
+
+        match self.users[idx].take() {
+            Some(user) => {
+                self.scores[idx] = None;
+                user::User::remove_from_chain(&mut self.users, idx, user.target_user_idx);
+            }
+            // already out of the chain
+            None if respawning => self.scores[idx] = None,
+            None => (),
+        }
+
+    }
+
+    // keeps idx from joining users until it's released, anything still addressed to it belongs to the user who left
+    pub fn reserve(&mut self, idx: usize) {
+        if !self.reserved.contains(&idx) {
+            self.reserved.push(idx);
+        }
+    }
+
+    pub fn release(&mut self, idx: usize) {
+        self.reserved.retain(|reserved_idx| *reserved_idx != idx);
+    }
+
+    // in the world or waiting to respawn, eliminated users aren't playing anymore
+    pub fn is_playing(&self, idx: usize) -> bool {
+        self.users.get(idx).is_some_and(|user| user.is_some()) || self.respawns.iter().any(|respawn| respawn.user_idx == idx)
+    }
+
+    pub fn respawn(&self, idx: usize) -> Option<&Respawn> {
+        self.respawns.iter().find(|respawn| respawn.user_idx == idx)
+    }
+
+    // the user with the most points (then kills, then fewest deaths), or the last one standing
+    pub fn winner(&self) -> Option<usize> {
+
+        if self.rules.last_hunter_standing {
+            return self.users.iter().flatten().map(|user| user.idx as usize).next();
+        }
+
+        self.scores
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, score)| score.map(|score| (idx, score)))
+            .max_by_key(|(_, score)| (score.points, score.kills, std::cmp::Reverse(score.deaths)))
+            .map(|(idx, _)| idx)
+
+    }
+
+    // sorted by user_idx
+    pub fn user_states(&self, room_idx: usize) -> Vec<protocol::UserState> {
+        self.users
+            .iter()
+            .filter_map(|user| user.as_ref())
+            .filter(|user| user.room_idx == room_idx)
+            .map(|user| protocol::UserState { user_idx: user.idx, rect: user.dynamic_entity.entity.to_rect(), protected: user.protected_ticks > 0 })
+            .collect()
+    }
+
+    // what everyone in the room sees this tick, as a keyframe
+    pub fn render(&self, room_idx: usize) -> protocol::Snapshot {
+        protocol::Snapshot {
+            tick: self.tick,
+            baseline_tick: None,
+            users: self.user_states(room_idx),
+            removed_users: Vec::new(),
+            bullet_paths: self.rooms_mut[room_idx].bullet_paths.iter().map(bullet::BulletPath::to_line).collect(),
+            projectiles: self.rooms_mut[room_idx].projectiles.iter().map(projectile::Projectile::to_point).collect(),
+            moving_platforms: self.rooms_mut[room_idx].moving_platforms.iter().map(kinematic::State::to_rect).collect(),
+        }
+    }
+
+    pub fn apply(&mut self, input: Input) {
+
+        match input {
+            Input::UpStart(idx) => { if let Some(user) = self.users[idx].as_mut() { user.jump_buffer_ticks = user.physics.jump_buffer_ticks; } },
+            Input::UpEnd(idx) => { if let Some(user) = self.users[idx].as_mut() { user.end_jump(); } },
+            Input::DownStart(idx) => { if let Some(user) = self.users[idx].as_mut() { user.holding_down = true; } }
+            Input::DownEnd(idx) => { if let Some(user) = self.users[idx].as_mut() { user.holding_down = false; } }
+            Input::LeftStart(idx) => { if let Some(user) = self.users[idx].as_mut() { user.holding_left = true; } },
+            Input::LeftEnd(idx) => { if let Some(user) = self.users[idx].as_mut() { user.holding_left = false; } },
+            Input::RightStart(idx) => { if let Some(user) = self.users[idx].as_mut() { user.holding_right = true; } },
+            Input::RightEnd(idx) => { if let Some(user) = self.users[idx].as_mut() { user.holding_right = false; } },
+            Input::Click(idx, view_tick, x, y) => {
+
+                // stunned users can't shoot, shooting gives up spawn protection
+                let user: &mut user::User = match self.users[idx].as_mut() {
+                    Some(user) if user.stun_ticks == 0 => user,
+                    _ => return,
+                };
+
+                // clicking on yourself doesn't aim anywhere, and doesn't use up a shot
+                let mut ray: ray::Ray = match ray::Ray::from_entity_and_position(&user.dynamic_entity.entity, x, y) {
+                    Some(ray) => ray,
+                    None => return,
+                };
+
+                let weapon: weapon::Weapon = match user.loadout.fire(&self.rules.weapons) {
+                    Some(weapon) => weapon,
+                    None => return,
+                };
+
+                user.protected_ticks = 0;
+
+                self.events.push(Event::Fired { user_idx: idx });
+
+                if weapon.spread > 0.0 {
+
+                    let angle: f32 = self.rng.gen_range(-weapon.spread..=weapon.spread);
+                    let (sin, cos) = angle.sin_cos();
+
+                    (ray.direction_x, ray.direction_y) = (ray.direction_x * cos - ray.direction_y * sin, ray.direction_x * sin + ray.direction_y * cos);
+
+                }
+
+                match weapon.kind {
+                    weapon::WeaponKind::Hitscan => self.rooms_mut[user.room_idx].bullets.push(bullet::Bullet {
+                        user_idx: idx,
+                        room_idx: user.room_idx,
+                        // clients can't see the future, and can't claim to have seen too far into the past
+                        rewind_tick: view_tick.clamp(self.tick.saturating_sub(self.max_rewind_ticks), self.tick),
+                        ray,
+                        range: weapon.max_range,
+                    }),
+                    weapon::WeaponKind::Projectile { speed, gravity_scale } => self.rooms_mut[user.room_idx].projectiles.push(
+                        projectile::Projectile::from_ray(&ray, idx, user.room_idx, speed, gravity_scale, weapon.max_range),
+                    ),
+                }
+
+            }
+            Input::Reload(idx) => { if let Some(user) = self.users[idx].as_mut() { user.loadout.reload(&self.rules.weapons); } },
+            Input::SwitchWeapon(idx, weapon_idx) => { if let Some(user) = self.users[idx].as_mut() { user.loadout.switch(weapon_idx, &self.rules.weapons); } },
+        }
+
+    }
+
+    // applies the inputs received since the last tick, then advances the world by one tick
+    // a finished match doesn't change anymore
+    pub fn tick(&mut self, inputs: impl IntoIterator<Item = Input>) {
+
+        if self.phase == rules::Phase::Ended {
+            return;
+        }
+
+        for input in inputs {
+            self.apply(input);
+        }
+
+        self.tick = self.tick.wrapping_add(1);
+
+        self.move_platforms();
+
+        while let Some(respawn_idx) = self.respawns.iter().position(|respawn| respawn.respawn_tick <= self.tick) {
+
+            let respawn: Respawn = self.respawns.remove(respawn_idx);
+
+            let target_user_idx: usize = match respawn.target_user_idx.filter(|idx| self.users[*idx].is_some()) {
+                // cut into the chain right in front of the target
+                Some(target_user_idx) => {
+
+                    if let Some(hunter) = self.users.iter_mut().flatten().find(|user| user.target_user_idx == target_user_idx) {
+                        hunter.target_user_idx = respawn.user_idx;
+                    }
+
+                    target_user_idx
+
+                }
+                None => user::User::get_target_idx(&mut self.users, respawn.user_idx, &mut self.rng),
+            };
+
+            self.spawn(respawn.user_idx, target_user_idx, respawn.killer_idx);
+
+        }
+
+        // shots can cross into other rooms, so every room's paths and projectiles are gathered before any are moved
+        for room_mut in self.rooms_mut.iter_mut() {
+            room_mut.bullet_paths.clear();
+        }
+
+        for room_idx in 0..self.rooms_mut.len() {
+
+            let bullets: Vec<bullet::Bullet> = std::mem::take(&mut self.rooms_mut[room_idx].bullets);
+
+            for bullet in bullets {
+
+                let (bullet_paths, hit_user_idx) = bullet.tick(&self.users, &self.rooms, &self.rooms_mut);
+
+                for (path_room_idx, bullet_path) in bullet_paths {
+                    self.rooms_mut[path_room_idx].bullet_paths.push(bullet_path);
+                }
+
+                if let Some(hit_user_idx) = hit_user_idx {
+                    self.hit(bullet.user_idx, hit_user_idx);
+                }
+
+            }
+
+        }
+
+        let projectiles: Vec<projectile::Projectile> = self.rooms_mut
+            .iter_mut()
+            .flat_map(|room_mut| std::mem::take(&mut room_mut.projectiles))
+            .collect();
+
+        for mut projectile in projectiles {
+
+            let (flying, hit_user_idx) = projectile.tick(&self.users, &self.rooms, &self.rooms_mut);
+
+            if let Some(hit_user_idx) = hit_user_idx {
+                self.hit(projectile.user_idx, hit_user_idx);
+            }
+
+            if flying {
+                self.rooms_mut[projectile.room_idx].projectiles.push(projectile);
+            }
+
+        }
+
+        for idx in 0..self.users.len() {
+
+            if self.users[idx].is_none() {
+                continue;
+            }
+
+            let (plucked, iter) = self.users.iter_plucked(idx).unwrap(); // none len = 0 (can't happen if in a loop)
+            let user: &mut user::User = plucked.as_mut().unwrap();
+            let users_iter = iter.filter_map(|u| u.as_ref());
+
+            user.tick(users_iter, &self.rooms, &self.rooms_mut, self.tick);
+            user.loadout.tick(&self.rules.weapons);
+
+        }
+
+        for (room_idx, room_mut) in self.rooms_mut.iter_mut().enumerate() {
+
+            let hitboxes: bullet::Hitboxes = self.users
+                .iter()
+                .filter_map(|user| user.as_ref())
+                .filter(|user| user.room_idx == room_idx)
+                .map(|user| (user.idx as usize, user.dynamic_entity.entity.clone()))
+                .collect();
+
+            room_mut.hitboxes.push(self.tick, hitboxes);
+
+        }
+
+        self.update_phase();
+
+    }
+
+    // shooting the target kills it and takes over its target, shooting anyone else is penalized unless it's self defence
+    // the shooter's target is whoever it is when the shot lands, it may have changed since a projectile was fired
+    fn hit(&mut self, shooter_idx: usize, hit_user_idx: usize) {
+
+        // the shooter may have been killed earlier this tick or while the shot was flying
+        let target_user_idx: usize = match self.users[shooter_idx].as_ref() {
+            Some(shooter) => shooter.target_user_idx,
+            None => return,
+        };
+
+        self.events.push(Event::Hit { user_idx: shooter_idx, hit_user_idx });
+
+        if self.users[hit_user_idx].as_ref().is_some_and(|user| user.protected_ticks > 0) {
+            return;
+        }
+
+        if hit_user_idx == target_user_idx {
+            return self.kill(hit_user_idx, Some(shooter_idx));
+        }
+
+        let hit_hunter: bool = self.users[hit_user_idx].as_ref().is_some_and(|user| user.target_user_idx == shooter_idx);
+
+        match self.rules.self_defence {
+            rules::SelfDefence::Allowed if hit_hunter => return,
+            rules::SelfDefence::Kill if hit_hunter => return self.kill(hit_user_idx, Some(shooter_idx)),
+            _ => (),
+        }
+
+        let penalties: &rules::Penalties = &self.rules.wrong_target;
+
+        if let Some(score) = self.scores[shooter_idx].as_mut().filter(|_| self.phase == rules::Phase::Live) {
+            score.wrong_target_hits += 1;
+            score.points -= penalties.points;
+        }
+
+        if penalties.kill {
+            return self.kill(shooter_idx, None);
+        }
+
+        let shooter: &mut user::User = self.users[shooter_idx].as_mut().unwrap(); // checked above
+
+        shooter.stun_ticks = shooter.stun_ticks.max(penalties.stun_ticks);
+        shooter.reveal_ticks = shooter.reveal_ticks.max(penalties.reveal_ticks);
+
+    }
+
+    // killing your target takes over its target, the victim rejoins the chain anywhere when they respawn
+    fn kill(&mut self, victim_idx: usize, killer_idx: Option<usize>) {
+
+        let killed_user: user::User = self.users[victim_idx].take().unwrap(); // only users in the simulation are killed
+
+        let hunted: bool = killer_idx
+            .and_then(|killer_idx| self.users[killer_idx].as_ref())
+            .is_some_and(|killer| killer.target_user_idx == victim_idx);
+
+        let live: bool = self.phase == rules::Phase::Live;
+
+        if live {
+
+            if let Some(score) = killer_idx.and_then(|killer_idx| self.scores[killer_idx].as_mut()) {
+                score.kill();
+            }
+
+            if let Some(score) = self.scores[victim_idx].as_mut() {
+                score.death();
+            }
+
+        }
+
+        user::User::remove_from_chain(&mut self.users, victim_idx, killed_user.target_user_idx);
+
+        if live && self.rules.last_hunter_standing {
+
+            if let Some(score) = self.scores[victim_idx].as_mut() {
+                score.eliminated = true;
+            }
+
+            self.events.push(Event::Eliminated { user_idx: victim_idx, room_idx: killed_user.room_idx });
+
+            return;
+
+        }
+
+        let respawn_tick: u32 = self.tick.saturating_add(self.rules.respawn_ticks);
+
+        self.respawns.push(Respawn {
+            user_idx: victim_idx,
+            killed_tick: self.tick,
+            respawn_tick,
+            room_idx: killed_user.room_idx,
+            killer_idx,
+            // killed in self defence, the victim goes back to hunting whoever shot them
+            target_user_idx: killer_idx.filter(|_| !hunted),
+        });
+
+        self.events.push(Event::Killed { user_idx: victim_idx, respawn_tick });
+
+    }
+
+    // puts the user at a spawn point away from their hunter and anyone else to avoid
+    fn spawn(&mut self, idx: usize, target_user_idx: usize, avoid_user_idx: Option<usize>) {
+
+        let hunter_idx: Option<usize> = self.users
+            .iter()
+            .flatten()
+            .find(|user| user.target_user_idx == idx)
+            .map(|user| user.idx as usize);
+
+        let avoid_user_idxs: Vec<usize> = hunter_idx.into_iter().chain(avoid_user_idx).collect();
+
+        let (room_idx, spawn) = spawn::select(&self.rooms, &self.users, &avoid_user_idxs, &mut self.rng);
+
+        let mut user: user::User = user::User::new(idx as u8, room_idx, spawn, target_user_idx, self.tick, self.physics);
+
+        user.protected_ticks = self.rules.spawn_protection_ticks;
+        user.loadout = weapon::Loadout::new(&self.rules.weapons);
+
+        self.users[idx] = Some(user);
+
+    }
+
+    // swaps in reloaded rooms (as many as before) and physics between ticks, users keep their rooms, scores and targets,
+    // only those the new geometry traps are moved to a spawn point, returns who was moved
+    pub fn hot_reload(&mut self, rooms: Arc<Vec<room::Room>>, physics: user::Physics) -> Vec<usize> {
+
+        self.rooms = rooms;
+        self.physics = physics;
+
+        self.move_platforms();
+
+        for user in self.users.iter_mut().flatten() {
+            user.physics = physics;
+            user.dynamic_entity.weight = physics.weight;
+        }
+
+        let trapped: Vec<usize> = self.users
+            .iter()
+            .flatten()
+            .filter(|user| self.rooms[user.room_idx].traps(&user.dynamic_entity.entity))
+            .map(|user| user.idx as usize)
+            .collect();
+
+        for idx in trapped.iter().copied() {
+
+            let hunter_idxs: Vec<usize> = self.users
+                .iter()
+                .flatten()
+                .filter(|user| user.target_user_idx == idx)
+                .map(|user| user.idx as usize)
+                .collect();
+
+            let (room_idx, spawn) = spawn::select(&self.rooms, &self.users, &hunter_idxs, &mut self.rng);
+
+            let user: &mut user::User = self.users[idx].as_mut().unwrap(); // found above
+
+            if user.room_idx != room_idx {
+                user.room_entry_tick = self.tick;
+            }
+
+            user.respawn(room_idx, spawn);
+
+        }
+
+        trapped
+
+    }
+
+    // platforms move before anything else each tick, so users stand on and shots hit where they are this tick
+    fn move_platforms(&mut self) {
+        for (room, room_mut) in self.rooms.iter().zip(self.rooms_mut.iter_mut()) {
+            room_mut.moving_platforms = room.moving_platforms.iter().map(|moving_platform| moving_platform.state_at(self.tick)).collect();
+        }
+    }
+
+    fn update_phase(&mut self) {
+
+        let user_count: usize = self.users.iter().flatten().count() + self.respawns.len();
+
+        match self.phase {
+            rules::Phase::Warmup => match (user_count >= self.rules.min_players, self.phase_end_tick) {
+                // the countdown starts over whenever someone leaves before it's done
+                (false, Some(_)) => self.set_phase(rules::Phase::Warmup, None),
+                (true, None) => self.set_phase(rules::Phase::Warmup, Some(self.tick.saturating_add(self.rules.warmup_ticks))),
+                (true, Some(end_tick)) if self.tick >= end_tick => {
+
+                    for score in self.scores.iter_mut().flatten() {
+                        *score = rules::Score::default();
+                    }
+
+                    self.set_phase(rules::Phase::Live, self.rules.time_limit_ticks.map(|ticks| self.tick.saturating_add(ticks)));
+
+                }
+                _ => (),
+            },
+            rules::Phase::Live => {
+
+                let score_reached: bool = self.rules.score_limit.is_some_and(|score_limit| {
+                    self.scores.iter().flatten().any(|score| score.points >= score_limit)
+                });
+
+                let time_up: bool = self.phase_end_tick.is_some_and(|end_tick| self.tick >= end_tick);
+
+                let last_standing: bool = self.rules.last_hunter_standing && user_count <= 1;
+
+                if score_reached || time_up || last_standing {
+                    self.set_phase(rules::Phase::Ended, None);
+                }
+
+            }
+            rules::Phase::Ended => (),
+        }
+
+    }
+
+    fn set_phase(&mut self, phase: rules::Phase, phase_end_tick: Option<u32>) {
+        self.phase = phase;
+        self.phase_end_tick = phase_end_tick;
+        self.events.push(Event::Phase);
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{ entity, replay };
+
+    fn simulation(players: usize) -> Simulation {
+
+        let rooms: Vec<room::Room> = room::load_rooms(std::path::Path::new("rooms")).expect("the default rooms load");
+
+        let mut simulation: Simulation = Simulation::new(Arc::new(rooms), rules::Rules::default(), user::Physics::default(), 7);
+
+        for idx in 0..players {
+            assert_eq!(simulation.join(), Some(idx));
+        }
+
+        simulation
+
+    }
+
+    fn target(simulation: &Simulation, idx: usize) -> usize {
+        simulation.users[idx].as_ref().expect("user is in the simulation").target_user_idx
+    }
+
+    // following targets from any user visits every user once before coming back
+    fn assert_one_chain(simulation: &Simulation) {
+
+        let idxs: Vec<usize> = (0..simulation.users.len()).filter(|idx| simulation.users[*idx].is_some()).collect();
+
+        let mut idx: usize = idxs[0];
+        let mut visited: Vec<usize> = Vec::new();
+
+        for _ in 0..idxs.len() {
+            visited.push(idx);
+            idx = target(simulation, idx);
+        }
+
+        visited.sort_unstable();
+
+        assert_eq!(idx, idxs[0]);
+        assert_eq!(visited, idxs);
+
+    }
+
+    // every user runs, jumps and shoots at whoever is next in line, switching weapons now and then
+    fn scripted_inputs(simulation: &Simulation) -> Vec<Input> {
+
+        let tick: u32 = simulation.tick;
+        let mut inputs: Vec<Input> = Vec::new();
+
+        for (idx, user) in simulation.users.iter().enumerate() {
+
+            if user.is_none() {
+                continue;
+            }
+
+            match (tick + idx as u32 * 13) % 90 {
+                0 => inputs.push(Input::RightStart(idx)),
+                30 => inputs.push(Input::RightEnd(idx)),
+                40 => inputs.push(Input::LeftStart(idx)),
+                70 => inputs.push(Input::LeftEnd(idx)),
+                _ => (),
+            }
+
+            match (tick + idx as u32 * 7) % 25 {
+                0 => inputs.push(Input::UpStart(idx)),
+                10 => inputs.push(Input::UpEnd(idx)),
+                _ => (),
+            }
+
+            if (tick + idx as u32).is_multiple_of(150) {
+                inputs.push(Input::SwitchWeapon(idx, (tick as usize / 150) % 2));
+            }
+
+            let aim: Option<&user::User> = simulation.users[(idx + 1) % simulation.users.len()].as_ref();
+
+            if let Some(aim) = aim.filter(|_| tick % 6 == idx as u32 % 6) {
+                inputs.push(Input::Click(idx, tick, aim.dynamic_entity.entity.x + 3.0, aim.dynamic_entity.entity.y + 1.0));
+            }
+
+        }
+
+        inputs
+
+    }
+
+    #[test]
+    fn same_seed_and_inputs_make_the_same_match() {
+
+        let mut simulations: [Simulation; 2] = [simulation(4), simulation(4)];
+        let mut fired: usize = 0;
+
+        for _ in 0..1200 {
+
+            let inputs: Vec<Input> = scripted_inputs(&simulations[0]);
+
+            assert_eq!(inputs, scripted_inputs(&simulations[1]));
+
+            for simulation in &mut simulations {
+                simulation.tick(inputs.iter().copied());
+            }
+
+            fired += simulations[0].events.iter().filter(|event| matches!(event, Event::Fired { .. })).count();
+
+            assert_eq!(simulations[0].events, simulations[1].events);
+
+            for simulation in &mut simulations {
+                simulation.events.clear();
+            }
+
+            assert_eq!(replay::checksum(&simulations[0]), replay::checksum(&simulations[1]));
+            assert_eq!(simulations[0].scores, simulations[1].scores);
+
+        }
+
+        // the spread of every shot was drawn from the seeded rng
+        assert!(fired > 0);
+
+    }
+
+    #[test]
+    fn clicking_on_yourself_fires_nothing() {
+
+        let mut simulation: Simulation = simulation(2);
+
+        // side by side in the same room, so a shot without a direction would have someone to hit
+        let shooter: &user::User = simulation.users[0].as_ref().unwrap();
+        let room_idx: usize = shooter.room_idx;
+        let shooter_entity: crate::entity::Entity = shooter.dynamic_entity.entity.clone();
+        let ammo: Vec<u32> = shooter.loadout.ammo.clone();
+
+        let other: &mut user::User = simulation.users[1].as_mut().unwrap();
+
+        other.room_idx = room_idx;
+        other.dynamic_entity.entity.x = shooter_entity.x + user::User::SIZE;
+        other.dynamic_entity.entity.y = shooter_entity.y;
+
+        let (x, y): (f32, f32) = (shooter_entity.x + shooter_entity.width * 0.5, shooter_entity.y + shooter_entity.height * 0.5);
+
+        simulation.tick([Input::Click(0, 0, x, y)]);
+
+        assert!(simulation.events.iter().all(|event| !matches!(event, Event::Fired { .. } | Event::Hit { .. })));
+        assert!(simulation.rooms_mut.iter().all(|room_mut| room_mut.bullets.is_empty() && room_mut.projectiles.is_empty()));
+        assert_eq!(simulation.users[0].as_ref().unwrap().loadout.ammo, ammo);
+
+    }
+
+    #[test]
+    fn last_user_leaving_empties_the_chain() {
+
+        let mut simulation: Simulation = simulation(1);
+
+        // a lone user hunts themselves, which is having no target
+        assert_eq!(target(&simulation, 0), 0);
+
+        simulation.leave(0);
+
+        assert!(simulation.users.iter().all(|user| user.is_none()));
+        assert_eq!(simulation.join(), Some(0));
+        assert_eq!(target(&simulation, 0), 0);
+
+    }
+
+    #[test]
+    fn user_left_alone_has_no_target() {
+
+        for leaving_idx in 0..2 {
+
+            let mut simulation: Simulation = simulation(2);
+
+            assert_eq!(target(&simulation, 0), 1);
+            assert_eq!(target(&simulation, 1), 0);
+
+            simulation.leave(leaving_idx);
+
+            let remaining_idx: usize = 1 - leaving_idx;
+
+            assert!(simulation.users[leaving_idx].is_none());
+            assert_eq!(target(&simulation, remaining_idx), remaining_idx);
+
+        }
+
+    }
+
+    #[test]
+    fn hunter_takes_over_the_leaving_users_target() {
+
+        let players: usize = 6;
+
+        for leaving_idx in 0..players {
+
+            let mut simulation: Simulation = simulation(players);
+
+            assert_one_chain(&simulation);
+
+            let hunter_idx: usize = (0..players).find(|idx| target(&simulation, *idx) == leaving_idx).unwrap();
+            let leaving_target_idx: usize = target(&simulation, leaving_idx);
+            let targets: Vec<usize> = (0..players).map(|idx| target(&simulation, idx)).collect();
+
+            simulation.leave(leaving_idx);
+
+            for idx in (0..players).filter(|idx| *idx != leaving_idx) {
+                match idx == hunter_idx {
+                    true => assert_eq!(target(&simulation, idx), leaving_target_idx),
+                    false => assert_eq!(target(&simulation, idx), targets[idx]),
+                }
+            }
+
+            assert_one_chain(&simulation);
+
+        }
+
+    }
+
+    #[test]
+    fn users_leave_until_one_is_left() {
+
+        let mut simulation: Simulation = simulation(5);
+
+        for leaving_idx in [2, 0, 4, 1] {
+
+            simulation.leave(leaving_idx);
+
+            assert_one_chain(&simulation);
+
+        }
+
+        assert_eq!(target(&simulation, 3), 3);
+
+    }
+
+    #[test]
+    fn solids_stop_riders_not_platforms() {
+
+        // the platform runs into the solid, whoever stands on it gets held back at the solid's side
+        let room: room::Room = room::Room {
+            gravity: 1.5,
+            bounds: room::Bounds { x_max: 200.0, y_max: 200.0 },
+            platforms: Vec::new(),
+            walls: Vec::new(),
+            solids: vec![entity::Entity { x: 120.0, y: 150.0, width: 20.0, height: 50.0 }],
+            moving_platforms: vec![kinematic::MovingPlatform {
+                width: 30.0,
+                height: 3.0,
+                path: kinematic::Path::Linear { from: kinematic::Point { x: 20.0, y: 170.0 }, to: kinematic::Point { x: 130.0, y: 170.0 }, ticks: 110 },
+            }],
+            doors: Vec::new(),
+            spawns: vec![room::Spawn { x: 30.0, y: 100.0 }],
+        };
+
+        let mut simulation: Simulation = Simulation::new(Arc::new(vec![room]), rules::Rules::default(), user::Physics::default(), 7);
+
+        assert_eq!(simulation.join(), Some(0));
+
+        let mut max_x: f32 = 0.0;
+
+        for _ in 0..150 {
+
+            simulation.tick(Vec::new());
+
+            let entity: &entity::Entity = &simulation.users[0].as_ref().expect("user is in the simulation").dynamic_entity.entity;
+
+            assert!(!entity.overlaps(&simulation.rooms[0].solids[0]), "rider pushed into the solid: {:?}", entity);
+
+            max_x = max_x.max(entity.x);
+
+        }
+
+        assert_eq!(max_x, 110.0);
+
+    }
+
+}
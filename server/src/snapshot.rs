@@ -0,0 +1,57 @@
+use std::collections::VecDeque;
+use crate::protocol;
+
+// per tick records of a room, kept so snapshots can be sent as deltas against whatever a client last acked
+// and so shots can be resolved against what the shooter saw
+pub struct History<T> {
+    records: VecDeque<(u32, T)>,
+}
+
+impl<T> History<T> {
+
+    pub const LEN: usize = 32;
+
+    pub fn new() -> Self {
+        Self {
+            records: VecDeque::with_capacity(Self::LEN),
+        }
+    }
+
+    pub fn push(&mut self, tick: u32, record: T) {
+
+        if self.records.len() == Self::LEN {
+            self.records.pop_front();
+        }
+
+        self.records.push_back((tick, record));
+
+    }
+
+    pub fn get(&self, tick: u32) -> Option<&T> {
+        self.records.iter().rev().find(|(record_tick, _)| *record_tick == tick).map(|(_, record)| record)
+    }
+
+    pub fn latest(&self) -> Option<&T> {
+        self.records.back().map(|(_, record)| record)
+    }
+
+}
+
+// users are sorted by user_idx in both slices
+pub fn delta(baseline: &[protocol::UserState], current: &[protocol::UserState]) -> (Vec<protocol::UserState>, Vec<u8>) {
+
+    let changed: Vec<protocol::UserState> = current
+        .iter()
+        .filter(|user| baseline.binary_search_by_key(&user.user_idx, |old| old.user_idx).map_or(true, |idx| baseline[idx] != **user))
+        .copied()
+        .collect();
+
+    let removed: Vec<u8> = baseline
+        .iter()
+        .filter(|old| current.binary_search_by_key(&old.user_idx, |user| user.user_idx).is_err())
+        .map(|old| old.user_idx)
+        .collect();
+
+    (changed, removed)
+
+}
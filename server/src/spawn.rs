@@ -0,0 +1,62 @@
+use rand::Rng;
+use crate::{ entity, room, user };
+
+// spawns within this fraction of the safest one are picked from at random, so users don't always appear in the same spot
+const SAFE_ENOUGH: f32 = 0.8;
+
+// picks a spawn point in any room as far as possible from the users to avoid, preferring points nobody is standing on,
+// a room without any of them in it is as far as it gets
+pub fn select(rooms: &[room::Room], users: &[Option<user::User>], avoid_user_idxs: &[usize], rng: &mut impl Rng) -> (usize, room::Spawn) {
+
+    let avoided: Vec<&user::User> = avoid_user_idxs
+        .iter()
+        .filter_map(|idx| users.get(*idx).and_then(|user| user.as_ref()))
+        .collect();
+
+    let mut candidates: Vec<(usize, room::Spawn, f32)> = Vec::new();
+
+    for (room_idx, room) in rooms.iter().enumerate() {
+        for spawn in &room.spawns {
+
+            let entity: entity::Entity = spawn.to_entity();
+
+            let occupied: bool = users
+                .iter()
+                .flatten()
+                .any(|user| user.room_idx == room_idx && user.dynamic_entity.entity.overlaps(&entity));
+
+            let distance: f32 = avoided
+                .iter()
+                .filter(|user| user.room_idx == room_idx)
+                .map(|user| distance(&user.dynamic_entity.entity, &entity))
+                .fold(f32::INFINITY, f32::min);
+
+            if !occupied {
+                candidates.push((room_idx, *spawn, distance));
+            }
+
+        }
+    }
+
+    // every spawn point has someone on it, spawn on top of them rather than not at all
+    if candidates.is_empty() {
+        for (room_idx, room) in rooms.iter().enumerate() {
+            for spawn in &room.spawns {
+                candidates.push((room_idx, *spawn, 0.0));
+            }
+        }
+    }
+
+    let safest: f32 = candidates.iter().map(|(_, _, distance)| *distance).fold(0.0, f32::max);
+
+    candidates.retain(|(_, _, distance)| *distance >= safest * SAFE_ENOUGH);
+
+    let (room_idx, spawn, _) = candidates[rng.gen_range(0..candidates.len())]; // rooms are validated to have a spawn
+
+    (room_idx, spawn)
+
+}
+
+fn distance(a: &entity::Entity, b: &entity::Entity) -> f32 {
+    ((a.x - b.x).powi(2) + (a.y - b.y).powi(2)).sqrt()
+}
@@ -1,6 +1,5 @@
-use rand::Rng;
-use tokio::sync::mpsc;
-use crate::{ entity, room, };
+use serde::{ Deserialize, Serialize };
+use crate::{ entity, kinematic, protocol, room, weapon };
 
 // when creating a new "game"
     // users_with_target usize = 0
@@ -31,8 +30,7 @@ pub struct User {
     // index 
     pub idx: u8,
     pub room_idx: usize,
-    // channels
-    pub send_to_client: mpsc::Sender<Vec<u8>>,
+    pub room_entry_tick: u32,
     // dynamic entity
     pub dynamic_entity: entity::DynamicEntity,
     // controls & state
@@ -42,42 +40,93 @@ pub struct User {
     pub holding_left: bool, 
     pub holding_right: bool, 
     pub holding_down: bool,
+    // the moving platform (in the user's room) the user landed on last tick, they're carried along with it
+    riding_platform_idx: Option<usize>,
+    // penalties for shooting the wrong user, stunned users keep their inputs but can't act on them
+    pub stun_ticks: u32,
+    // the user's hunter is told where they are
+    pub reveal_ticks: u32,
+    // just spawned, bullets can't kill or penalize anyone for hitting the user until it runs out or they shoot
+    pub protected_ticks: u32,
+    // armed by the simulation when it spawns the user
+    pub loadout: weapon::Loadout,
+    pub physics: Physics,
 }
 
-impl User {
+// how users move, forces are divided by weight and speeds are pixels per tick
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Physics {
+    // how long a jump pressed in the air is remembered for landing
+    pub jump_buffer_ticks: u8,
+    // how long after running off something a user can still jump
+    pub coyote_ticks: u8,
+    pub jump_force: f32,
+    // what's left of the upward speed when a jump is let go early
+    pub jump_cutoff: f32,
+    pub run_start_force: f32,
+    pub run_end_force: f32,
+    pub run_max_speed: f32,
+    pub weight: f32,
+}
+
+impl Default for Physics {
+
+    fn default() -> Self {
+        Self {
+            jump_buffer_ticks: 5,
+            coyote_ticks: 3,
+            jump_force: -40.0,
+            jump_cutoff: 0.5,
+            run_start_force: 1.0,
+            run_end_force: 2.0,
+            run_max_speed: 5.0,
+            weight: 3.0,
+        }
+    }
 
-    pub const JUMP_BUFFER_TICKS: u8 = 5;
-    const COYOTE_TICKS: u8 = 3;
+}
 
-    const JUMP_FORCE: f32 = -40.0;
-    const JUMP_CUTOFF: f32 = 0.5;
+impl Physics {
 
-    const RUN_START_FORCE: f32 = 1.0;
-    const RUN_END_FORCE: f32 = 2.0;
-    const RUN_MAX_SPEED: f32 = 5.0;
+    // the first field users couldn't move with and what it should be
+    pub fn invalid_field(&self) -> Option<(&'static str, &'static str)> {
 
-    pub fn new(idx: u8, room_idx: usize, target_user_idx: usize, send_to_client: mpsc::Sender<Vec<u8>>) -> Self {
+        let fields: [(&'static str, bool, &'static str); 6] = [
+            ("physics.jump_force", self.jump_force.is_finite() && self.jump_force < 0.0, "below 0"),
+            ("physics.jump_cutoff", self.jump_cutoff >= 0.0 && self.jump_cutoff <= 1.0, "between 0 and 1"),
+            ("physics.run_start_force", self.run_start_force.is_finite() && self.run_start_force > 0.0, "above 0"),
+            ("physics.run_end_force", self.run_end_force.is_finite() && self.run_end_force > 0.0, "above 0"),
+            ("physics.run_max_speed", self.run_max_speed.is_finite() && self.run_max_speed > 0.0, "above 0"),
+            ("physics.weight", self.weight.is_finite() && self.weight > 0.0, "above 0"),
+        ];
 
-        let entity: entity::Entity = entity::Entity {
-            x: 0.0,
-            y: 0.0,
-            width: 10.0, 
-            height: 10.0, 
-        };
+        fields.iter().find(|(_, valid, _)| !valid).map(|(field, _, expected)| (*field, *expected))
+
+    }
+
+}
+
+impl User {
+
+    pub const SIZE: f32 = 10.0;
+
+    pub fn new(idx: u8, room_idx: usize, spawn: room::Spawn, target_user_idx: usize, tick: u32, physics: Physics) -> Self {
+
+        let entity: entity::Entity = spawn.to_entity();
 
         let dynamic_entity: entity::DynamicEntity = entity::DynamicEntity {
             entity,
             dx: 0.0,
             dy: 0.0,
-            weight: 3.0,
+            weight: physics.weight,
         };
 
         Self {
             // index 
             idx,
             room_idx,
-            // channels
-            send_to_client,
+            room_entry_tick: tick,
             // entity
             dynamic_entity,
             // controls & state
@@ -87,26 +136,67 @@ impl User {
             holding_left: false,
             holding_right: false,
             holding_down: false,
+            riding_platform_idx: None,
+            stun_ticks: 0,
+            reveal_ticks: 0,
+            protected_ticks: 0,
+            loadout: weapon::Loadout::default(),
+            physics,
         }
 
     }
 
-    pub fn respawn(&mut self, room_idx: usize) {
+    pub fn respawn(&mut self, room_idx: usize, spawn: room::Spawn) {
         self.room_idx = room_idx;
         self.dynamic_entity.dx = 0.0;
         self.dynamic_entity.dy = 0.0;
-        self.dynamic_entity.entity.x = 0.0;
-        self.dynamic_entity.entity.y = 0.0;
+        self.dynamic_entity.entity.x = spawn.x;
+        self.dynamic_entity.entity.y = spawn.y;
         self.jump_buffer_ticks = 0;
         self.coyote_ticks = 0;
         self.holding_left = false;
         self.holding_right = false;
         self.holding_down = false;
+        self.riding_platform_idx = None;
     }
 
-    pub fn tick<'a, 'b>(&'a mut self, users: impl Iterator<Item = &'b User>) {
+    // standing on something, or just ran off of it and can still jump
+    pub fn grounded(&self) -> bool {
+        self.coyote_ticks > 0
+    }
+
+    pub fn movement(&self) -> protocol::Movement {
+        protocol::Movement {
+            x: self.dynamic_entity.entity.x,
+            y: self.dynamic_entity.entity.y,
+            dx: self.dynamic_entity.dx,
+            dy: self.dynamic_entity.dy,
+            coyote_ticks: self.coyote_ticks,
+            jump_buffer_ticks: self.jump_buffer_ticks,
+            stun_ticks: self.stun_ticks.min(u16::MAX as u32) as u16,
+        }
+    }
+
+    pub fn tick<'a, 'b>(&'a mut self, users: impl Iterator<Item = &'b User>, rooms: &[room::Room], rooms_mut: &[room::RoomMut], tick: u32) {
+
+        let room: &room::Room = &rooms[self.room_idx];
+        let moving_platforms: &[kinematic::State] = &rooms_mut[self.room_idx].moving_platforms;
 
-        let room: &room::Room = &room::ROOMS[self.room_idx];
+        let stunned: bool = self.stun_ticks > 0;
+
+        self.stun_ticks = self.stun_ticks.saturating_sub(1);
+        self.reveal_ticks = self.reveal_ticks.saturating_sub(1);
+        self.protected_ticks = self.protected_ticks.saturating_sub(1);
+
+        // a jump pressed while stunned is dropped, not buffered until it wears off
+        if stunned {
+            self.jump_buffer_ticks = 0;
+        }
+
+        // the platform has already moved this tick, the user stays where they were on it and lands on it again below
+        if let Some(state) = self.riding_platform_idx.take().and_then(|idx| moving_platforms.get(idx)) {
+            self.ride(state, room);
+        }
 
         let mut horizontal_collision: Option<entity::HorizontalCollision> = None;
         let mut vertical_collision: Option<entity::VerticalCollision> = None;
@@ -146,7 +236,7 @@ impl User {
 
         }
 
-        for entity in room.platforms {
+        for entity in &room.platforms {
             
             let (time, _, vertical) = self.dynamic_entity.swept_collision(entity);
             
@@ -167,7 +257,54 @@ impl User {
 
         }
 
-        for door in room.doors {
+        for (idx, state) in moving_platforms.iter().enumerate() {
+
+            let (time, _, vertical) = self.dynamic_entity.swept_collision(&state.entity);
+
+            if self.dynamic_entity.dy > 0.0 {
+                if let Some(direction) = vertical {
+                    if self.holding_down {
+                        self.holding_down = false;
+                    } else if time < vertical_time {
+                        vertical_time = time;
+                        vertical_collision = Some(entity::VerticalCollision {
+                            variant: entity::CollisionVariant::MovingPlatform(idx, &state.entity),
+                            direction,
+                            time,
+                        });
+                    }
+                }
+            }
+
+        }
+
+        for entity in &room.solids {
+
+            let (time, horizontal, vertical) = self.dynamic_entity.swept_collision(entity);
+
+            if let Some(direction) = horizontal {
+                if time < horizontal_time {
+                    horizontal_time = time;
+                    horizontal_collision = Some(entity::HorizontalCollision {
+                        variant: entity::CollisionVariant::Solid(entity),
+                        direction,
+                        time,
+                    });
+                }
+            } else if let Some(direction) = vertical {
+                if time < vertical_time {
+                    vertical_time = time;
+                    vertical_collision = Some(entity::VerticalCollision {
+                        variant: entity::CollisionVariant::Solid(entity),
+                        direction,
+                        time,
+                    });
+                }
+            }
+
+        }
+
+        for door in &room.doors {
             
             let (time, horizontal, vertical) = self.dynamic_entity.swept_collision(&door.entity);
             
@@ -207,26 +344,14 @@ impl User {
                 self.dynamic_entity.entity.x += self.dynamic_entity.dx;
                 
                 match self.dynamic_entity.dx.partial_cmp(&0.0) {
-                    Some(std::cmp::Ordering::Greater) => {
-
-                        if self.holding_right == false {
-                            self.end_run_right();
-                        }
-    
-                    }
-                    Some(std::cmp::Ordering::Less) => {
-
-                        if self.holding_left == false {
-                            self.end_run_left();
-                        }
-    
-                    }
+                    Some(std::cmp::Ordering::Greater) if !self.holding_right || stunned => self.end_run_right(),
+                    Some(std::cmp::Ordering::Less) if !self.holding_left || stunned => self.end_run_left(),
                     _ => (),
                 }
 
             }
             Some(horizontal_collision) => {
-                self.handle_horizontal_collision(horizontal_collision, &room.bounds);
+                self.handle_horizontal_collision(horizontal_collision, &room.bounds, rooms, tick);
             }
         }
 
@@ -249,11 +374,11 @@ impl User {
 
         // maybe check if grounded and do something different if in air
         // or move inside horizontal collision :: none
-        if self.holding_left {
+        if self.holding_left && !stunned {
             self.run_left();
         }
 
-        if self.holding_right {
+        if self.holding_right && !stunned {
             self.run_right();
         }
 
@@ -269,7 +394,32 @@ impl User {
 
     }
 
-    fn handle_horizontal_collision(&mut self, horizontal_collision: entity::HorizontalCollision, bounds: &room::Bounds) {
+    // carried along by a moving platform, solids stop the user (not the platform) like they stop a move of their own
+    fn ride(&mut self, state: &kinematic::State, room: &room::Room) {
+
+        let horizontal: entity::DynamicEntity = entity::DynamicEntity { entity: self.dynamic_entity.entity.clone(), dx: state.dx, dy: 0.0, weight: self.dynamic_entity.weight };
+
+        let x: f32 = room.solids.iter().fold(horizontal.entity.x + state.dx, |x, solid| match horizontal.swept_collision(solid).1 {
+            Some(entity::HorizontalCollisionDirection::Right) => x.min(solid.x - horizontal.entity.width),
+            Some(entity::HorizontalCollisionDirection::Left) => x.max(solid.x + solid.width),
+            None => x,
+        });
+
+        self.dynamic_entity.entity.x = x.clamp(0.0, room.bounds.x_max - horizontal.entity.width);
+
+        let vertical: entity::DynamicEntity = entity::DynamicEntity { entity: self.dynamic_entity.entity.clone(), dx: 0.0, dy: state.dy, weight: self.dynamic_entity.weight };
+
+        let y: f32 = room.solids.iter().fold(vertical.entity.y + state.dy, |y, solid| match vertical.swept_collision(solid).2 {
+            Some(entity::VerticalCollisionDirection::Down) => y.min(solid.y - vertical.entity.height),
+            Some(entity::VerticalCollisionDirection::Up) => y.max(solid.y + solid.height),
+            None => y,
+        });
+
+        self.dynamic_entity.entity.y = y;
+
+    }
+
+    fn handle_horizontal_collision(&mut self, horizontal_collision: entity::HorizontalCollision, bounds: &room::Bounds, rooms: &[room::Room], tick: u32) {
 
         match horizontal_collision.direction {
             entity::HorizontalCollisionDirection::Left => {
@@ -283,14 +433,19 @@ impl User {
                         self.dynamic_entity.entity.x = entity.x + entity.width;
                         self.dynamic_entity.dx = 0.0;
                     }
-                    entity::CollisionVariant::Platform(_) => {
+                    entity::CollisionVariant::Platform(_) | entity::CollisionVariant::MovingPlatform(..) => {
                         unreachable!();
                     }
+                    entity::CollisionVariant::Solid(entity) => {
+                        self.dynamic_entity.entity.x = entity.x + entity.width;
+                        self.dynamic_entity.dx = 0.0;
+                    }
                     entity::CollisionVariant::Door(door) => {
                         let room_idx: usize = door.room_idx;
-                        let entity: &entity::Entity = &room::ROOMS[room_idx].doors[door.door_idx].entity;
+                        let entity: &entity::Entity = &rooms[room_idx].doors[door.door_idx].entity;
 
                         self.room_idx = room_idx;
+                        self.room_entry_tick = tick;
                         self.dynamic_entity.entity.x = entity.x - self.dynamic_entity.entity.width;
                     }
                 }
@@ -307,14 +462,19 @@ impl User {
                         self.dynamic_entity.entity.x = entity.x - self.dynamic_entity.entity.width;
                         self.dynamic_entity.dx = 0.0;
                     }
-                    entity::CollisionVariant::Platform(_) => {
+                    entity::CollisionVariant::Platform(_) | entity::CollisionVariant::MovingPlatform(..) => {
                         unreachable!();
                     }
+                    entity::CollisionVariant::Solid(entity) => {
+                        self.dynamic_entity.entity.x = entity.x - self.dynamic_entity.entity.width;
+                        self.dynamic_entity.dx = 0.0;
+                    }
                     entity::CollisionVariant::Door(door) => {
                         let room_idx: usize = door.room_idx;
-                        let entity: &entity::Entity = &room::ROOMS[room_idx].doors[door.door_idx].entity;
+                        let entity: &entity::Entity = &rooms[room_idx].doors[door.door_idx].entity;
 
                         self.room_idx = room_idx;
+                        self.room_entry_tick = tick;
                         self.dynamic_entity.entity.x = entity.x + entity.width;
                     }
                 }
@@ -336,9 +496,13 @@ impl User {
                     entity::CollisionVariant::User(entity) => {
                         self.dynamic_entity.entity.y = entity.y - self.dynamic_entity.entity.height;
                     }
-                    entity::CollisionVariant::Platform(entity) => {
+                    entity::CollisionVariant::Platform(entity) | entity::CollisionVariant::Solid(entity) => {
                         self.dynamic_entity.entity.y = entity.y - self.dynamic_entity.entity.height;
                     }
+                    entity::CollisionVariant::MovingPlatform(idx, entity) => {
+                        self.dynamic_entity.entity.y = entity.y - self.dynamic_entity.entity.height;
+                        self.riding_platform_idx = Some(idx);
+                    }
                     entity::CollisionVariant::Door(door) => {
                         self.dynamic_entity.entity.y = door.entity.y - self.dynamic_entity.entity.height;
                     }
@@ -349,7 +513,7 @@ impl User {
                 if self.jump_buffer_ticks > 0 {
                     self.jump();
                 } else {
-                    self.coyote_ticks = Self::COYOTE_TICKS;
+                    self.coyote_ticks = self.physics.coyote_ticks;
                 }
 
             }
@@ -362,9 +526,12 @@ impl User {
                     entity::CollisionVariant::User(entity) => {
                         self.dynamic_entity.entity.y = entity.y + entity.height;
                     }
-                    entity::CollisionVariant::Platform(_) => {
+                    entity::CollisionVariant::Platform(_) | entity::CollisionVariant::MovingPlatform(..) => {
                         unreachable!();
                     }
+                    entity::CollisionVariant::Solid(entity) => {
+                        self.dynamic_entity.entity.y = entity.y + entity.height;
+                    }
                     entity::CollisionVariant::Door(door) => {
                         self.dynamic_entity.entity.y = door.entity.y + door.entity.height;
                     }
@@ -382,31 +549,31 @@ impl User {
     }
 
     fn jump(&mut self) {
-        self.dynamic_entity.dy = Self::JUMP_FORCE / self.dynamic_entity.weight;
+        self.dynamic_entity.dy = self.physics.jump_force / self.dynamic_entity.weight;
         self.coyote_ticks = 0;
         self.jump_buffer_ticks = 0;
     }
 
     pub fn end_jump(&mut self) {
         // maybe check if actually jumping?
-        self.dynamic_entity.dy *= Self::JUMP_CUTOFF;
+        self.dynamic_entity.dy *= self.physics.jump_cutoff;
     }
 
     fn run_left(&mut self) {
 
-        let run_speed: f32 = self.dynamic_entity.dx - Self::RUN_START_FORCE / self.dynamic_entity.weight;
+        let run_speed: f32 = self.dynamic_entity.dx - self.physics.run_start_force / self.dynamic_entity.weight;
         
-        self.dynamic_entity.dx = if run_speed > -Self::RUN_MAX_SPEED {
+        self.dynamic_entity.dx = if run_speed > -self.physics.run_max_speed {
             run_speed
         } else {
-            -Self::RUN_MAX_SPEED
+            -self.physics.run_max_speed
         };
 
     }
 
     fn end_run_left(&mut self) {
 
-        let run_speed: f32 = self.dynamic_entity.dx + Self::RUN_END_FORCE / self.dynamic_entity.weight;
+        let run_speed: f32 = self.dynamic_entity.dx + self.physics.run_end_force / self.dynamic_entity.weight;
 
         self.dynamic_entity.dx = if run_speed < 0.0 {
             run_speed
@@ -418,19 +585,19 @@ impl User {
 
     fn run_right(&mut self) {
         
-        let run_speed: f32 = self.dynamic_entity.dx + Self::RUN_START_FORCE / self.dynamic_entity.weight;
+        let run_speed: f32 = self.dynamic_entity.dx + self.physics.run_start_force / self.dynamic_entity.weight;
         
-        self.dynamic_entity.dx = if run_speed < Self::RUN_MAX_SPEED {
+        self.dynamic_entity.dx = if run_speed < self.physics.run_max_speed {
             run_speed
         } else {
-            Self::RUN_MAX_SPEED
+            self.physics.run_max_speed
         };
 
     }
 
     fn end_run_right(&mut self) {
 
-        let run_speed: f32 = self.dynamic_entity.dx - Self::RUN_END_FORCE / self.dynamic_entity.weight;
+        let run_speed: f32 = self.dynamic_entity.dx - self.physics.run_end_force / self.dynamic_entity.weight;
 
         self.dynamic_entity.dx = if run_speed > 0.0 {
             run_speed
@@ -440,16 +607,15 @@ impl User {
         
     }
 
-    pub fn get_target_idx(users: &mut [Option<User>], new_user_idx: usize) -> usize {
+    pub fn get_target_idx(users: &mut [Option<User>], new_user_idx: usize, rng: &mut impl rand::Rng) -> usize {
 
-        let no_users: bool = users.iter().any(|user| user.is_some()) == false;
+        let no_users: bool = !users.iter().any(|user| user.is_some());
 
+        // the only user hunts themselves until someone else joins
         if no_users {
-            return 0;
+            return new_user_idx;
         }
 
-        let mut rng: rand::rngs::ThreadRng = rand::thread_rng();
-
         loop {
 
             let random_user_idx: usize = rng.gen_range(0..users.len());
@@ -469,4 +635,20 @@ impl User {
 
     }
 
+    // whoever was hunting the removed user inherits their target, a lone user ends up hunting themselves
+    pub fn remove_from_chain(users: &mut [Option<User>], removed_user_idx: usize, removed_target_user_idx: usize) {
+
+        // the removed user was the only one in the chain
+        if removed_target_user_idx == removed_user_idx {
+            return;
+        }
+
+        for user in users.iter_mut().flatten() {
+            if user.target_user_idx == removed_user_idx {
+                user.target_user_idx = removed_target_user_idx;
+            }
+        }
+
+    }
+
 }
\ No newline at end of file
@@ -0,0 +1,78 @@
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use crate::{ config, lobby, log, room, user };
+
+// how often the files are checked for changes
+const POLL_INTERVAL_MS: u64 = 1000;
+
+// reloads the rooms and the config file's physics whenever one of their files changes and hands them to the lobby,
+// files that fail to load are reported and skipped until they change again
+pub async fn watch(args: Vec<String>, config: Arc<config::ServerConfig>, send_to_lobby: mpsc::Sender<lobby::Message>) {
+
+    let span: log::Span = log::Span::root("watch").with("room_dir", config.room_dir.display());
+
+    let mut modified: Vec<(std::path::PathBuf, Option<std::time::SystemTime>)> = modified_times(&config);
+    let mut timer: tokio::time::Interval = tokio::time::interval(tokio::time::Duration::from_millis(POLL_INTERVAL_MS));
+
+    loop {
+
+        timer.tick().await;
+
+        let latest: Vec<(std::path::PathBuf, Option<std::time::SystemTime>)> = modified_times(&config);
+
+        if latest == modified {
+            continue;
+        }
+
+        modified = latest;
+
+        let rooms: Vec<room::Room> = match room::load_rooms(&config.room_dir) {
+            Ok(rooms) => rooms,
+            Err(err) => {
+                span.error("failed to reload rooms", &[("err", &err)]);
+                continue;
+            }
+        };
+
+        let physics: user::Physics = match config::ServerConfig::load(&args) {
+            Ok(reloaded) => reloaded.physics,
+            Err(err) => {
+                span.error("failed to reload config", &[("err", &err)]);
+                continue;
+            }
+        };
+
+        span.info("reloaded rooms and physics", &[("rooms", &rooms.len())]);
+
+        if send_to_lobby.send(lobby::Message::HotReload { rooms: Arc::new(rooms), physics }).await.is_err() {
+            return span.error("no lobby to reload", &[]);
+        }
+
+    }
+
+}
+
+// every room file and the config file, a file that can't be read has no time so it changes when it comes back
+fn modified_times(config: &config::ServerConfig) -> Vec<(std::path::PathBuf, Option<std::time::SystemTime>)> {
+
+    let mut paths: Vec<std::path::PathBuf> = match std::fs::read_dir(&config.room_dir) {
+        Ok(read_dir) => read_dir
+            .flatten()
+            .map(|dir_entry| dir_entry.path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "json"))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+
+    paths.sort();
+    paths.extend(config.path.clone());
+
+    paths
+        .into_iter()
+        .map(|path| {
+            let modified: Option<std::time::SystemTime> = std::fs::metadata(&path).and_then(|metadata| metadata.modified()).ok();
+            (path, modified)
+        })
+        .collect()
+
+}
@@ -0,0 +1,162 @@
+use serde::{ Deserialize, Serialize };
+use crate::protocol;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Weapon {
+    pub kind: WeaponKind,
+    // ticks between shots
+    pub cooldown_ticks: u32,
+    // shots before reloading, 0 never reloads
+    pub magazine: u32,
+    pub reload_ticks: u32,
+    // how far a shot goes before it's gone
+    pub max_range: f32,
+    // max angle (radians) a shot strays either way from where it was aimed
+    pub spread: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum WeaponKind {
+    // resolved the tick it's fired, against where users were when the shooter saw them
+    Hitscan,
+    // moves speed pixels per tick and falls with gravity_scale times the room's gravity
+    Projectile { speed: f32, gravity_scale: f32 },
+}
+
+// a user's weapons, indexed like rules::Rules::weapons
+#[derive(Debug, Clone, Default)]
+pub struct Loadout {
+    pub weapon_idx: usize,
+    pub ammo: Vec<u32>,
+    pub cooldown_ticks: u32,
+    // reloading the current weapon while above zero
+    pub reload_ticks: u32,
+}
+
+impl Weapon {
+
+    pub const PISTOL: Weapon = Weapon {
+        kind: WeaponKind::Hitscan,
+        cooldown_ticks: 15,
+        magazine: 6,
+        reload_ticks: 90,
+        max_range: 200.0,
+        spread: 0.02,
+    };
+
+    pub const BOW: Weapon = Weapon {
+        kind: WeaponKind::Projectile { speed: 8.0, gravity_scale: 0.1 },
+        cooldown_ticks: 40,
+        magazine: 0,
+        reload_ticks: 0,
+        max_range: 400.0,
+        spread: 0.0,
+    };
+
+    // shots that go nowhere, or NaN anywhere, can't be resolved
+    pub fn is_valid(&self) -> bool {
+
+        let projectile: bool = match self.kind {
+            WeaponKind::Hitscan => true,
+            WeaponKind::Projectile { speed, gravity_scale } => speed.is_finite() && speed > 0.0 && gravity_scale.is_finite(),
+        };
+
+        projectile && self.max_range.is_finite() && self.max_range > 0.0 && self.spread.is_finite() && self.spread >= 0.0
+
+    }
+
+}
+
+impl Loadout {
+
+    pub fn new(weapons: &[Weapon]) -> Self {
+        Self {
+            weapon_idx: 0,
+            ammo: weapons.iter().map(|weapon| weapon.magazine).collect(),
+            cooldown_ticks: 0,
+            reload_ticks: 0,
+        }
+    }
+
+    pub fn tick(&mut self, weapons: &[Weapon]) {
+
+        self.cooldown_ticks = self.cooldown_ticks.saturating_sub(1);
+
+        if self.reload_ticks > 0 {
+
+            self.reload_ticks -= 1;
+
+            if self.reload_ticks == 0 {
+                self.ammo[self.weapon_idx] = weapons[self.weapon_idx].magazine;
+            }
+
+        }
+
+    }
+
+    // the weapon fired, None while cooling down, reloading or unarmed
+    pub fn fire(&mut self, weapons: &[Weapon]) -> Option<Weapon> {
+
+        let weapon: Weapon = *weapons.get(self.weapon_idx)?;
+
+        if self.cooldown_ticks > 0 || self.reload_ticks > 0 {
+            return None;
+        }
+
+        self.cooldown_ticks = weapon.cooldown_ticks;
+
+        if weapon.magazine > 0 {
+
+            self.ammo[self.weapon_idx] -= 1;
+
+            // reloads as soon as it's empty
+            if self.ammo[self.weapon_idx] == 0 {
+                self.reload_ticks = weapon.reload_ticks.max(1);
+            }
+
+        }
+
+        Some(weapon)
+
+    }
+
+    pub fn reload(&mut self, weapons: &[Weapon]) {
+
+        let weapon: &Weapon = match weapons.get(self.weapon_idx) {
+            Some(weapon) => weapon,
+            None => return,
+        };
+
+        if self.reload_ticks == 0 && self.ammo[self.weapon_idx] < weapon.magazine {
+            self.reload_ticks = weapon.reload_ticks.max(1);
+        }
+
+    }
+
+    // switching cancels a reload
+    pub fn switch(&mut self, weapon_idx: usize, weapons: &[Weapon]) {
+
+        if weapon_idx >= weapons.len() || weapon_idx == self.weapon_idx {
+            return;
+        }
+
+        self.weapon_idx = weapon_idx;
+        self.reload_ticks = 0;
+
+        // an empty weapon that was switched away from mid reload starts over
+        if weapons[weapon_idx].magazine > 0 && self.ammo[weapon_idx] == 0 {
+            self.reload_ticks = weapons[weapon_idx].reload_ticks.max(1);
+        }
+
+    }
+
+    pub fn to_protocol(&self) -> protocol::Loadout {
+        protocol::Loadout {
+            weapon_idx: self.weapon_idx as u8,
+            ammo: self.ammo.get(self.weapon_idx).map_or(0, |ammo| (*ammo).min(u16::MAX as u32) as u16),
+            cooldown_ticks: self.cooldown_ticks.min(u16::MAX as u32) as u16,
+            reload_ticks: self.reload_ticks.min(u16::MAX as u32) as u16,
+        }
+    }
+
+}